@@ -1,71 +1,1339 @@
 //! Application rendering and state management
 //!
-//! Keybindings:
+//! Default keybindings. They can be changed in keymap.toml (see `keymap`), and the Keys sidebar
+//! lists the ones in use:
 //!
 //! alt+c: switch sidebar to canvas mode
 //! alt+f: switch sidebar to file mode
-//! tab: cycle palette forwards
-//! shift+tab: cycle palette backwards
+//! alt+r: switch sidebar to rom mode
+//! alt+v: switch sidebar to vram mode
+//! alt+s: switch sidebar to sprite mode
+//! alt+k: switch sidebar to the list of keybindings
+//! o: toggle onion skinning
+//! shift+f: fill at the cursor (shift+click fills with the mouse)
+//! right click a palette color: pick the secondary color for dithering
+//! ctrl+drag: select a rectangle, escape to clear it
+//! hjkl, w/b, gg/G, v, i, y/p/d: vim-style cursor movement and editing, see `vim`
+//! ctrl+b: turn the selection into a custom brush
+//! ':': open the command line, see `command`
+//! shift+j: cycle palette forwards
+//! shift+k: cycle palette backwards
+//! ctrl+z / ctrl+shift+z: undo / redo, kept separately for each tab
+//! ctrl+c / ctrl+v: copy the selection and paste it, into any tab
+//! ctrl+n / ctrl+w: open a new tab / close this one
+//! ctrl+pagedown / ctrl+pageup: next / previous tab
 //! TODO: MORE!
 
 use std::fs;
-use eframe::{App, Frame};
-use eframe::egui::{CentralPanel, Color32, ComboBox, Context, Id, Pos2, Sense, SidePanel, TextEdit};
-use crate::paint::Canvas;
-
-pub mod shortcut {
-    use eframe::egui::{Key, KeyboardShortcut, Modifiers};
-
-    pub(crate) const PALETTE_FORWARD: KeyboardShortcut = KeyboardShortcut::new(Modifiers::SHIFT, Key::J);
-    pub(crate) const PALETTE_BACKWARD: KeyboardShortcut = KeyboardShortcut::new(Modifiers::SHIFT, Key::K);
-    pub(crate) const SIDEBAR_FILE: KeyboardShortcut = KeyboardShortcut::new(Modifiers::ALT, Key::F);
-    pub(crate) const SIDEBAR_CANVAS: KeyboardShortcut = KeyboardShortcut::new(Modifiers::ALT, Key::C);
-    pub(crate) const CANVAS_SIZE_FIELD: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::I);
-}
-
-pub mod action {
-    use eframe::egui::{Key, KeyboardShortcut, Modifiers};
-
-    pub(crate) const CURSOR_LEFT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::L);
-    pub(crate) const CURSOR_RIGHT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::H);
-    pub(crate) const CURSOR_UP: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::K);
-    pub(crate) const CURSOR_DOWN: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::J);
-    pub(crate) const CURSOR_PAINT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::F);
-}
+use std::path::{Path, PathBuf};
+use eframe::{egui, App, Frame};
+use eframe::egui::{Button, CentralPanel, Checkbox, Color32, ComboBox, Context, DragValue, Event, Id, Key, Modifiers, Pos2, Rect, ScrollArea, Sense, SidePanel, Slider, Stroke, TextEdit, TopBottomPanel, Ui, Vec2};
+use crate::Error;
+use crate::command::{Command, CommandLine, ExportKind};
+use crate::document::Document;
+use crate::keymap::{self, Action, Keymap};
+use crate::paint::{BrushShape, Canvas, Dither, Grid, Palette, Symmetry};
+use crate::rom::{self, Mapping, Region, Rom};
+use crate::rom::patch::PatchFormat;
+use crate::serde::asm::{self, Assembler};
+use crate::serde::compress::{self, Compression};
+use crate::serde::dump;
+use crate::serde::palette::{self, PaletteFormat};
+use crate::serde::project;
+use crate::serde::{self, TileOrder};
+use crate::serde::source::{self, SourceLanguage};
+use crate::settings::Settings;
+use crate::recovery::{self, Autosave, Session};
+use crate::toast::Toasts;
+use crate::vim::Vim;
+use crate::sprite::{Metasprite, Obj, ObjSize, SpriteExport};
 
 #[derive(Default)]
 pub struct SnesPaintApp {
-    canvas: Canvas,
+    /// The document in the active tab.
+    doc: Document,
+    /// Every other tab, in order, as if `doc` had been taken out of the list.
+    others: Vec<Document>,
+    /// Which tab `doc` is.
+    active: usize,
+    /// A tab with unsaved changes that's waiting on the user to confirm closing it.
+    closing: Option<usize>,
+    /// The window was asked to close with unsaved tabs, and the user hasn't said whether to.
+    quitting: bool,
+    /// The user chose to quit without saving, so the next close request goes through.
+    quit_confirmed: bool,
     side_bar: SideBar,
+    rom: Option<Rom>,
+    vram: Option<Vec<u8>>,
+    cgram: Option<Vec<Color32>>,
+    metasprite: Metasprite,
+    /// Whether the animation preview is playing.
+    playing: bool,
+    vim: Vim,
+    command_line: CommandLine,
+    keymap: Keymap,
+    settings: Settings,
+    /// What's on disk, to tell when `settings` needs writing.
+    saved_settings: Settings,
+    autosave: Autosave,
+    toasts: Toasts,
+    /// Work left by a session that crashed, until the user restores or discards it.
+    recovered: Option<Vec<Session>>,
 }
 
-#[derive(Default, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
 pub enum SideBarType {
     #[default]
     File,
     Canvas,
+    Rom,
+    Vram,
+    Sprite,
+    Keys,
+    #[allow(dead_code)]
     Layer,
     // ...
 }
 
+impl SideBarType {
+    pub const ALL: [SideBarType; 6] = [
+        SideBarType::File,
+        SideBarType::Canvas,
+        SideBarType::Rom,
+        SideBarType::Vram,
+        SideBarType::Sprite,
+        SideBarType::Keys,
+    ];
+}
+
+impl std::fmt::Display for SideBarType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            SideBarType::File => "File",
+            SideBarType::Canvas => "Canvas",
+            SideBarType::Rom => "ROM",
+            SideBarType::Vram => "VRAM",
+            SideBarType::Sprite => "Sprite",
+            SideBarType::Keys => "Keys",
+            SideBarType::Layer => "Layer",
+        };
+        write!(f, "{}", str)
+    }
+}
+
 #[derive(Default)]
 pub struct SideBar {
     side_bar_type: SideBarType,
     canvas_width_field: String,
     canvas_height_field: String,
+    palette_format: PaletteFormat,
+    keep_palette_bpp: bool,
+    assembler: Assembler,
+    source_language: SourceLanguage,
+    compression: Compression,
+    /// How tiles are laid out in saved and exported VRAM data.
+    tile_order: TileOrder,
+    /// Uncompressed and compressed size of the last compressed save.
+    last_compression: Option<(usize, usize)>,
+    rom: RomSideBar,
+    vram: VramSideBar,
+    sprite: SpriteSideBar,
+}
+
+pub struct SpriteSideBar {
+    /// Screen position of the metasprite's origin in the OAM export.
+    origin: (i32, i32),
+    export: SpriteExport,
+}
+
+impl Default for SpriteSideBar {
+    fn default() -> Self {
+        SpriteSideBar {
+            origin: (128, 112),
+            export: SpriteExport::default(),
+        }
+    }
+}
+
+pub struct RomSideBar {
+    address_field: String,
+    /// Whether the address field is a SNES address rather than a file offset.
+    snes_address: bool,
+    bpp: usize,
+    tiles_wide_field: String,
+    tiles_high_field: String,
+    patch_format: PatchFormat,
+}
+
+pub struct VramSideBar {
+    /// VRAM word address, in hex.
+    address_field: String,
+    bpp: usize,
+    tiles_wide_field: String,
+    tiles_high_field: String,
+    sub_palette: usize,
+}
+
+impl Default for VramSideBar {
+    fn default() -> Self {
+        VramSideBar {
+            address_field: "0".to_owned(),
+            bpp: 4,
+            tiles_wide_field: "16".to_owned(),
+            tiles_high_field: "8".to_owned(),
+            sub_palette: 0,
+        }
+    }
+}
+
+impl Default for RomSideBar {
+    fn default() -> Self {
+        RomSideBar {
+            address_field: "0".to_owned(),
+            snes_address: false,
+            bpp: 4,
+            tiles_wide_field: "4".to_owned(),
+            tiles_high_field: "4".to_owned(),
+            patch_format: PatchFormat::default(),
+        }
+    }
 }
 
 impl SnesPaintApp {
     pub fn new(_cc: &eframe::CreationContext<'_>) -> SnesPaintApp {
         let mut app = Self::default();
-        app.canvas.palette.set_color(1, Color32::BLACK);
-        app.canvas.set_pos(Pos2::new(50.0, 50.0));
+        match Keymap::load() {
+            Ok(keymap) => app.keymap = keymap,
+            Err(e) => app.toasts.error("Couldn't load keymap, using the defaults", &e),
+        }
+        match Settings::load() {
+            Ok(settings) => app.settings = settings,
+            Err(e) => app.toasts.error("Couldn't load settings", &e),
+        }
+        app.restore_settings();
+        if let Some(dir) = recovery::dir() {
+            match Autosave::start(dir) {
+                Ok((autosave, recovered)) => {
+                    app.autosave = autosave;
+                    app.recovered = recovered;
+                }
+                Err(e) => app.toasts.error("Couldn't start autosave", &e),
+            }
+        }
+        if app.settings.palette.is_none() {
+            app.doc.canvas.palette.set_color(1, Color32::BLACK);
+        }
+        app.doc.canvas.set_pos(Pos2::new(50.0, 50.0));
+        // none of that setup is worth undoing
+        let canvas = std::mem::take(&mut app.doc.canvas);
+        app.doc = Document::new(canvas, None);
         app
     }
 }
 
+impl SnesPaintApp {
+    /// Puts the UI back how `settings` remembers it.
+    fn restore_settings(&mut self) {
+        let settings = &self.settings;
+        let side_bar = &mut self.side_bar;
+        side_bar.side_bar_type = settings.side_bar;
+        side_bar.palette_format = settings.palette_format;
+        side_bar.keep_palette_bpp = settings.keep_palette_bpp;
+        side_bar.assembler = settings.assembler;
+        side_bar.source_language = settings.source_language;
+        side_bar.compression = settings.compression;
+        side_bar.tile_order = settings.tile_order;
+        self.doc.canvas.pixel_width = settings.pixel_width;
+        if let Some(colors) = &settings.palette {
+            self.doc.canvas.palette = Palette::from_colors(colors);
+        }
+        self.saved_settings = self.settings.clone();
+    }
+
+    /// Copies the UI's state into `settings`, writing them out if anything changed.
+    fn store_settings(&mut self) {
+        let settings = &mut self.settings;
+        let side_bar = &self.side_bar;
+        settings.side_bar = side_bar.side_bar_type;
+        settings.palette_format = side_bar.palette_format;
+        settings.keep_palette_bpp = side_bar.keep_palette_bpp;
+        settings.assembler = side_bar.assembler;
+        settings.source_language = side_bar.source_language;
+        settings.compression = side_bar.compression;
+        settings.tile_order = side_bar.tile_order;
+        settings.pixel_width = self.doc.canvas.pixel_width;
+        settings.palette = Some(self.doc.canvas.palette.colors().to_vec());
+        if self.settings != self.saved_settings {
+            if let Err(e) = self.settings.save() {
+                self.toasts.error("Couldn't save settings", &e);
+            }
+            self.saved_settings = self.settings.clone();
+        }
+    }
+
+    /// Offers back what a crashed session was working on.
+    fn recovery_window(&mut self, ctx: &Context) {
+        let Some(sessions) = &self.recovered else { return };
+        let mut choice = None;
+        egui::Window::new("Recover unsaved work?").collapsible(false).resizable(false).show(ctx, |ui| {
+            ui.label("SNES Paint didn't close properly last time.");
+            let paths: Vec<String> = sessions.iter()
+                .filter_map(|session| session.project_path.as_ref())
+                .map(|path| path.display().to_string())
+                .collect();
+            if !paths.is_empty() {
+                ui.label(format!("It was editing {}.", paths.join(", ")));
+            }
+            let untitled = sessions.len() - paths.len();
+            if untitled > 0 {
+                ui.label(format!("Untitled documents: {untitled}"));
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Restore").clicked() {
+                    choice = Some(true);
+                }
+                if ui.button("Discard").clicked() {
+                    choice = Some(false);
+                }
+            });
+        });
+        match choice {
+            Some(true) => {
+                let Some(sessions) = self.recovered.take() else { return };
+                let (pos, pixel_width) = (self.doc.canvas.pos(), self.settings.pixel_width);
+                let mut documents = sessions.into_iter().map(|session| {
+                    let mut canvas = session.canvas;
+                    canvas.pixel_width = pixel_width;
+                    canvas.set_pos(pos);
+                    Document::recovered(canvas, session.project_path)
+                });
+                // the blank document the app started with isn't worth keeping around
+                if self.others.is_empty() && self.doc.is_blank() {
+                    if let Some(first) = documents.next() {
+                        self.doc = first;
+                    }
+                }
+                for doc in documents {
+                    self.add_document(doc);
+                }
+            }
+            Some(false) => {
+                self.recovered = None;
+                if let Err(e) = self.autosave.discard() {
+                    self.toasts.error("Couldn't remove the old autosave", &e);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Opens a project in a new tab, or switches to it if it's already open.
+    fn open_project(&mut self, file: PathBuf) -> Result<(), Error> {
+        let canonical = file.canonicalize().ok();
+        let open = (0..self.tab_count()).find(|n| {
+            self.tab(*n).path.as_ref().and_then(|p| p.canonicalize().ok()).is_some_and(|p| Some(p) == canonical)
+        });
+        if let Some(n) = open {
+            self.switch_to(n);
+            return Ok(());
+        }
+
+        let mut canvas = project::open(&file)?;
+        canvas.pixel_width = self.settings.pixel_width;
+        canvas.set_pos(self.doc.canvas.pos());
+        self.settings.add_recent(&file);
+        // a PNG is an import, so a plain :w mustn't save a project over it
+        let path = (!project::is_png(&file)).then_some(file);
+        let doc = Document::new(canvas, path);
+        if self.doc.is_blank() {
+            self.doc = doc;
+        } else {
+            self.add_document(doc);
+        }
+        Ok(())
+    }
+
+    fn save_project(&mut self, file: PathBuf) -> Result<(), Error> {
+        project::save(&file, &self.doc.canvas)?;
+        self.settings.add_recent(&file);
+        self.doc.path = Some(file);
+        self.doc.history.mark_saved(&self.doc.canvas);
+        Ok(())
+    }
+}
+
+impl SnesPaintApp {
+    fn tab_count(&self) -> usize {
+        self.others.len() + 1
+    }
+
+    fn tab(&self, n: usize) -> &Document {
+        match n.cmp(&self.active) {
+            std::cmp::Ordering::Less => &self.others[n],
+            std::cmp::Ordering::Equal => &self.doc,
+            std::cmp::Ordering::Greater => &self.others[n - 1],
+        }
+    }
+
+    fn switch_to(&mut self, n: usize) {
+        if n == self.active || n >= self.tab_count() {
+            return;
+        }
+        let doc = std::mem::take(&mut self.doc);
+        self.others.insert(self.active, doc);
+        self.doc = self.others.remove(n);
+        self.active = n;
+    }
+
+    /// Adds a tab at the end and switches to it.
+    fn add_document(&mut self, doc: Document) {
+        let previous = std::mem::replace(&mut self.doc, doc);
+        self.others.insert(self.active, previous);
+        self.active = self.others.len();
+    }
+
+    /// A blank canvas the same size, zoom and palette as the current one.
+    fn new_document(&mut self) {
+        let current = &self.doc.canvas;
+        let mut canvas = Canvas::new();
+        if let Err(e) = canvas.set_size(current.grid().width(), current.grid().height()) {
+            self.toasts.error("Couldn't make a new canvas", &e);
+            return;
+        }
+        canvas.palette = current.palette.clone();
+        canvas.pixel_width = current.pixel_width;
+        canvas.set_pos(current.pos());
+        self.add_document(Document::new(canvas, None));
+    }
+
+    /// Shows imported tiles in an untitled tab, so saving can't write them over a project. The
+    /// current tab gets reused if it's untitled with nothing unsaved, like when paging through a
+    /// ROM.
+    fn open_import(&mut self, mut canvas: Canvas, rom_region: Option<Region>) {
+        canvas.pixel_width = self.doc.canvas.pixel_width;
+        canvas.set_pos(self.doc.canvas.pos());
+        let mut doc = Document::new(canvas, None);
+        doc.rom_region = rom_region;
+        if self.doc.path.is_none() && !self.doc.history.is_dirty() {
+            self.doc = doc;
+        } else {
+            self.add_document(doc);
+        }
+    }
+
+    /// Closes a tab, asking first if it has unsaved changes unless `force`.
+    fn close_document(&mut self, n: usize, force: bool) {
+        if n >= self.tab_count() {
+            return;
+        }
+        if !force && self.tab(n).history.is_dirty() {
+            self.closing = Some(n);
+            return;
+        }
+        if n != self.active {
+            self.others.remove(if n < self.active { n } else { n - 1 });
+            if n < self.active {
+                self.active -= 1;
+            }
+        } else if self.others.is_empty() {
+            // always keep one tab open
+            let mut canvas = Canvas::new();
+            canvas.pixel_width = self.doc.canvas.pixel_width;
+            canvas.set_pos(self.doc.canvas.pos());
+            self.doc = Document::new(canvas, None);
+        } else {
+            self.active = Ord::min(self.active, self.others.len() - 1);
+            self.doc = self.others.remove(self.active);
+        }
+    }
+
+    /// One tab per document, with a star on the ones with unsaved changes.
+    fn tab_bar(&mut self, ui: &mut Ui) {
+        let mut switch = None;
+        let mut close = None;
+        ui.horizontal_wrapped(|ui| {
+            for n in 0..self.tab_count() {
+                let doc = self.tab(n);
+                let name = if doc.history.is_dirty() { format!("{} *", doc.name()) } else { doc.name() };
+                let hover = doc.path.as_ref().map_or("not saved yet".to_owned(), |p| p.display().to_string());
+                if ui.selectable_label(n == self.active, name).on_hover_text(hover).clicked() {
+                    switch = Some(n);
+                }
+                if ui.small_button("x").on_hover_text("Close").clicked() {
+                    close = Some(n);
+                }
+                ui.separator();
+            }
+            if ui.button("+").on_hover_text("New tab").clicked() {
+                self.new_document();
+            }
+        });
+        if let Some(n) = switch {
+            self.switch_to(n);
+        }
+        if let Some(n) = close {
+            self.close_document(n, false);
+        }
+    }
+
+    /// Asks before throwing away a tab's unsaved changes.
+    fn close_window(&mut self, ctx: &Context) {
+        let Some(n) = self.closing else { return };
+        if n >= self.tab_count() {
+            self.closing = None;
+            return;
+        }
+        let mut choice = None;
+        egui::Window::new("Close without saving?").collapsible(false).resizable(false).show(ctx, |ui| {
+            ui.label(format!("{} has unsaved changes.", self.tab(n).name()));
+            ui.horizontal(|ui| {
+                if ui.button("Close").clicked() {
+                    choice = Some(true);
+                }
+                if ui.button("Cancel").clicked() {
+                    choice = Some(false);
+                }
+            });
+        });
+        if let Some(close) = choice {
+            self.closing = None;
+            if close {
+                self.close_document(n, true);
+            }
+        }
+    }
+
+    /// Asks before quitting with unsaved changes in any tab.
+    fn quit_window(&mut self, ctx: &Context) {
+        if !self.quitting {
+            return;
+        }
+        let dirty: Vec<String> = (0..self.tab_count())
+            .filter(|n| self.tab(*n).history.is_dirty())
+            .map(|n| self.tab(n).name())
+            .collect();
+        let mut choice = None;
+        egui::Window::new("Quit without saving?").collapsible(false).resizable(false).show(ctx, |ui| {
+            ui.label(format!("Unsaved changes in {}.", dirty.join(", ")));
+            ui.horizontal(|ui| {
+                if ui.button("Quit").clicked() {
+                    choice = Some(true);
+                }
+                if ui.button("Cancel").clicked() {
+                    choice = Some(false);
+                }
+            });
+        });
+        if let Some(quit) = choice {
+            self.quitting = false;
+            if quit {
+                self.quit_confirmed = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        }
+    }
+
+    /// Tab and undo shortcuts, which work whatever the sidebar or vim are doing.
+    fn document_keys(&mut self, ctx: &Context) {
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+        let keymap = &self.keymap;
+        let pressed = |action: Action| ctx.input_mut(|i| i.consume_shortcut(&keymap.get(action)));
+        // ctrl+z matches ctrl+shift+z too, so redo has to go first
+        if pressed(Action::Redo) {
+            self.doc.history.redo(&mut self.doc.canvas);
+        }
+        if pressed(Action::Undo) {
+            self.doc.history.undo(&mut self.doc.canvas);
+        }
+        let new = pressed(Action::NewDocument);
+        let close = pressed(Action::CloseDocument);
+        let next = pressed(Action::NextDocument);
+        let previous = pressed(Action::PreviousDocument);
+        let count = self.tab_count();
+        if next {
+            self.switch_to((self.active + 1) % count);
+        }
+        if previous {
+            self.switch_to((self.active + count - 1) % count);
+        }
+        if new {
+            self.new_document();
+        }
+        if close {
+            self.close_document(self.active, false);
+        }
+    }
+}
+
+impl SnesPaintApp {
+    fn rom_side_bar(&mut self, ui: &mut Ui) {
+        if ui.button("Open ROM...").clicked() {
+            let file = self.settings.dialog()
+                .add_filter("SNES ROMs", &["sfc", "smc"])
+                .pick_file();
+            if let Some(file) = file {
+                self.settings.remember_dir(&file);
+                match Rom::load(&file) {
+                    Ok(loaded) => {
+                        self.rom = Some(loaded);
+                        // tiles loaded from the last ROM can't be written back to this one
+                        self.doc.rom_region = None;
+                        for doc in &mut self.others {
+                            doc.rom_region = None;
+                        }
+                    }
+                    Err(e) => self.toasts.error(&format!("Couldn't open ROM {}", file.display()), &e),
+                }
+            }
+        }
+        let Some(rom) = &mut self.rom else {
+            ui.label("No ROM open");
+            return;
+        };
+
+        ui.label(format!("Title: {}", rom.title()));
+        ui.label(format!(
+            "{} KiB{}",
+            rom.data().len() / 1024,
+            if rom.has_copier_header() { ", copier header" } else { "" },
+        ));
+        // the header detection can guess wrong on homebrew and odd dumps
+        let mut mapping = rom.mapping();
+        ComboBox::from_label("Mapping")
+            .selected_text(mapping.to_string())
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut mapping, Mapping::LoRom, Mapping::LoRom.to_string());
+                ui.selectable_value(&mut mapping, Mapping::HiRom, Mapping::HiRom.to_string());
+            }
+        );
+        if mapping != rom.mapping() {
+            if let Err(e) = rom.set_mapping(mapping) {
+                self.toasts.error(&format!("Couldn't switch to {mapping}"), &e);
+            }
+        }
+        ui.separator();
+
+        let fields = &mut self.side_bar.rom;
+        ui.horizontal(|ui| {
+            ui.label("Address:");
+            ui.add(TextEdit::singleline(&mut fields.address_field).desired_width(60.0));
+            ui.selectable_value(&mut fields.snes_address, false, "PC");
+            ui.selectable_value(&mut fields.snes_address, true, "SNES");
+        });
+        ComboBox::from_label("Tile Depth")
+            .selected_text(format!("{} BPP", fields.bpp))
+            .show_ui(ui, |ui| {
+                for bpp in [1, 2, 3, 4, 8] {
+                    ui.selectable_value(&mut fields.bpp, bpp, format!("{bpp} BPP"));
+                }
+            }
+        );
+        ui.horizontal(|ui| {
+            ui.label("Tiles:");
+            ui.add(TextEdit::singleline(&mut fields.tiles_wide_field).desired_width(25.0));
+            ui.label("x");
+            ui.add(TextEdit::singleline(&mut fields.tiles_high_field).desired_width(25.0));
+        });
+
+        let mut step = None;
+        ui.horizontal(|ui| {
+            if ui.button("< Prev").clicked() {
+                step = Some(-1);
+            }
+            if ui.button("Load Tiles").clicked() {
+                step = Some(0);
+            }
+            if ui.button("Next >").clicked() {
+                step = Some(1);
+            }
+        });
+        if let Some(step) = step {
+            self.load_rom_tiles(step);
+        }
+
+        if let (Some(rom), Some(region)) = (&self.rom, &self.doc.rom_region) {
+            ui.label(format!(
+                "Showing {} tiles at {:06X} (${:06X})",
+                region.tiles,
+                region.offset,
+                rom.pc_to_snes(region.offset),
+            ));
+            if ui.button("Write Tiles to ROM").clicked() {
+                if let Err(e) = self.write_rom_tiles() {
+                    self.toasts.error("Couldn't write tiles to ROM", &e);
+                }
+            }
+        }
+        ui.separator();
+
+        let format = &mut self.side_bar.rom.patch_format;
+        ComboBox::from_label("Save As")
+            .selected_text(format.to_string())
+            .show_ui(ui, |ui| {
+                for f in PatchFormat::ALL {
+                    ui.selectable_value(format, f, f.to_string());
+                }
+            }
+        );
+        let format = *format;
+        let Some(rom) = &mut self.rom else { return };
+        if rom.is_modified() {
+            ui.label("ROM has unsaved changes");
+        }
+        if ui.button("Save ROM Changes...").clicked() {
+            let file = match (format, rom.path()) {
+                (PatchFormat::InPlace, Some(path)) => Some(path.to_owned()),
+                _ => {
+                    let mut dialog = self.settings.dialog().add_filter(format.to_string(), &[format.extension()]);
+                    if let Some(name) = rom.path().and_then(|p| p.file_stem()) {
+                        dialog = dialog.set_file_name(format!("{}.{}", name.to_string_lossy(), format.extension()));
+                    }
+                    dialog.save_file()
+                }
+            };
+            if let Some(file) = file {
+                self.settings.remember_dir(&file);
+                match rom.export(format).and_then(|data| Ok(fs::write(file, data)?)) {
+                    // a patch doesn't change the ROM on disk, so keep diffing against it
+                    Ok(()) if format == PatchFormat::InPlace => rom.mark_saved(),
+                    Ok(()) => {}
+                    Err(e) => self.toasts.error("Couldn't save ROM", &e),
+                }
+            }
+        }
+    }
+
+    /// Re-encodes the canvas and writes it back where it was loaded from in the ROM.
+    fn write_rom_tiles(&mut self) -> Result<(), Error> {
+        let (Some(rom), Some(region)) = (&mut self.rom, &self.doc.rom_region) else {
+            return Ok(());
+        };
+        let (width, height) = region.canvas_size();
+        if (self.doc.canvas.grid().width(), self.doc.canvas.grid().height()) != (width, height) {
+            return Err(Error::InvalidCanvasSize(self.doc.canvas.grid().width(), self.doc.canvas.grid().height()));
+        }
+        if self.doc.canvas.palette.bpp() != region.bpp {
+            return Err(Error::InvalidRom(format!("tiles were loaded as {}bpp, canvas is {}bpp", region.bpp, self.doc.canvas.palette.bpp())));
+        }
+        let (mut tiles, _) = serde::write_out(self.doc.canvas.grid(), &self.doc.canvas.palette)?;
+        // a page cut short by the end of the ROM gets padded out on the canvas; those extra
+        // tiles were never in the ROM
+        if tiles.len() > region.len() && tiles[region.len()..].iter().all(|b| *b == 0) {
+            tiles.truncate(region.len());
+        }
+        rom.write_tiles(region, &tiles)
+    }
+
+    /// Decodes the tiles at the address in the ROM sidebar into the canvas, after moving the
+    /// address `step` pages (one page being the requested number of tiles) forward or back.
+    fn load_rom_tiles(&mut self, step: isize) {
+        let Some(rom) = &self.rom else { return };
+        let fields = &mut self.side_bar.rom;
+        let (Ok(tiles_wide), Ok(tiles_high)) = (fields.tiles_wide_field.parse::<usize>(), fields.tiles_high_field.parse::<usize>()) else {
+            let count = format!("{}x{}", fields.tiles_wide_field, fields.tiles_high_field);
+            self.toasts.error("Couldn't load tiles", &Error::Parse("tile count", count));
+            return;
+        };
+        if tiles_wide == 0 || tiles_high == 0 {
+            return;
+        }
+        let offset = match rom::parse_address(&fields.address_field) {
+            Some(address) if fields.snes_address => rom.snes_to_pc(address),
+            Some(address) => Some(address as usize),
+            None => None,
+        };
+        let Some(offset) = offset else {
+            self.toasts.error("Couldn't load tiles", &Error::Parse("address", fields.address_field.clone()));
+            return;
+        };
+
+        let page = tiles_wide * tiles_high * serde::tile_size(fields.bpp);
+        let offset = offset.saturating_add_signed(step * page as isize).min(rom.data().len().saturating_sub(1));
+        let len = Ord::min(page, rom.data().len() - offset);
+        let Some(bytes) = rom.read(offset, len) else { return };
+        fields.address_field = if fields.snes_address {
+            format!("{:06X}", rom.pc_to_snes(offset))
+        } else {
+            format!("{offset:X}")
+        };
+
+        let grid = serde::read_tiles(bytes, fields.bpp, tiles_wide);
+        let mut palette = self.doc.canvas.palette.clone();
+        if let Err(e) = palette.set_bpp(fields.bpp) {
+            self.toasts.error("Couldn't load tiles", &e);
+            return;
+        }
+        let region = Region {
+            offset,
+            bpp: fields.bpp,
+            tiles_wide,
+            tiles: len / serde::tile_size(fields.bpp),
+        };
+        self.open_import(Canvas::from_parts(Box::new(grid), palette), Some(region));
+    }
+}
+
+impl SnesPaintApp {
+    fn vram_side_bar(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Open VRAM Dump...").clicked() {
+                let file = self.settings.dialog().add_filter("VRAM dump", &["bin", "dmp", "vram"]).pick_file();
+                if let Some(file) = file {
+                    self.settings.remember_dir(&file);
+                    match dump::load_vram(&file) {
+                        Ok(vram) => self.vram = Some(vram),
+                        Err(e) => self.toasts.error(&format!("Couldn't open VRAM dump {}", file.display()), &e),
+                    }
+                }
+            }
+            ui.label(if self.vram.is_some() { "loaded" } else { "none" });
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Open CGRAM Dump...").clicked() {
+                let file = self.settings.dialog().add_filter("CGRAM dump", &["bin", "dmp", "cgr", "pal"]).pick_file();
+                if let Some(file) = file {
+                    self.settings.remember_dir(&file);
+                    match dump::load_cgram(&file) {
+                        Ok(cgram) => self.cgram = Some(cgram),
+                        Err(e) => self.toasts.error(&format!("Couldn't open CGRAM dump {}", file.display()), &e),
+                    }
+                }
+            }
+            ui.label(if self.cgram.is_some() { "loaded" } else { "none" });
+        });
+        ui.separator();
+
+        let fields = &mut self.side_bar.vram;
+        ui.horizontal(|ui| {
+            ui.label("Word address:");
+            ui.add(TextEdit::singleline(&mut fields.address_field).desired_width(50.0));
+        });
+        ComboBox::from_label("Tile Depth")
+            .selected_text(format!("{} BPP", fields.bpp))
+            .show_ui(ui, |ui| {
+                for bpp in [2, 4, 8] {
+                    ui.selectable_value(&mut fields.bpp, bpp, format!("{bpp} BPP"));
+                }
+            }
+        );
+        ui.horizontal(|ui| {
+            ui.label("Tiles:");
+            ui.add(TextEdit::singleline(&mut fields.tiles_wide_field).desired_width(25.0));
+            ui.label("x");
+            ui.add(TextEdit::singleline(&mut fields.tiles_high_field).desired_width(25.0));
+        });
+        let sub_palettes = dump::sub_palettes(fields.bpp);
+        fields.sub_palette = Ord::min(fields.sub_palette, sub_palettes - 1);
+        let sub_palette = fields.sub_palette;
+        ComboBox::from_label("Sub-palette")
+            .selected_text(fields.sub_palette.to_string())
+            .show_ui(ui, |ui| {
+                for i in 0..sub_palettes {
+                    ui.selectable_value(&mut fields.sub_palette, i, i.to_string());
+                }
+            }
+        );
+
+        // recolor straight away, that's the whole point of picking a sub-palette
+        if ui.button("Show Tiles").clicked() || sub_palette != fields.sub_palette {
+            self.show_vram_tiles();
+        }
+    }
+
+    /// Decodes tiles from the VRAM dump into the canvas, colored with the chosen CGRAM
+    /// sub-palette (or the current palette if there's no CGRAM dump).
+    fn show_vram_tiles(&mut self) {
+        let Some(vram) = &self.vram else { return };
+        let fields = &self.side_bar.vram;
+        let Ok(address) = usize::from_str_radix(fields.address_field.trim().trim_start_matches('$'), 16) else {
+            self.toasts.error("Couldn't load tiles", &Error::Parse("word address", fields.address_field.clone()));
+            return;
+        };
+        let (Ok(tiles_wide), Ok(tiles_high)) = (fields.tiles_wide_field.parse::<usize>(), fields.tiles_high_field.parse::<usize>()) else {
+            let count = format!("{}x{}", fields.tiles_wide_field, fields.tiles_high_field);
+            self.toasts.error("Couldn't load tiles", &Error::Parse("tile count", count));
+            return;
+        };
+        if tiles_wide == 0 || tiles_high == 0 {
+            return;
+        }
+
+        let bytes = dump::vram_tiles(vram, address, fields.bpp, tiles_wide * tiles_high);
+        let palette = match &self.cgram {
+            Some(cgram) => dump::sub_palette(cgram, fields.bpp, fields.sub_palette),
+            None => {
+                let mut palette = self.doc.canvas.palette.clone();
+                palette.set_bpp(fields.bpp).map(|()| palette)
+            }
+        };
+        let palette = match palette {
+            Ok(palette) => palette,
+            Err(e) => {
+                self.toasts.error("Couldn't load tiles", &e);
+                return;
+            }
+        };
+        let canvas = Canvas::from_parts(Box::new(serde::read_tiles(&bytes, fields.bpp, tiles_wide)), palette);
+        self.open_import(canvas, None);
+    }
+}
+
+impl SnesPaintApp {
+    fn sprite_side_bar(&mut self, ui: &mut Ui) {
+        self.metasprite_preview(ui);
+        ui.separator();
+
+        // canvas tiles are numbered the way an export with the current tile order puts them in VRAM
+        if ui.button("Add OBJ at Cursor").clicked() {
+            let (x, y) = self.doc.canvas.cursor();
+            let grid = self.doc.canvas.grid();
+            let bpp = self.doc.canvas.palette.bpp();
+            match serde::vram_position(x, y, grid.width(), grid.height(), bpp, self.side_bar.tile_order) {
+                Some((tile, _)) => self.metasprite.objs.push(Obj { tile: tile as u16 & 0x1ff, ..Default::default() }),
+                None => self.toasts.info(format!("The tile under the cursor isn't exported in {} order", self.side_bar.tile_order)),
+            }
+        }
+
+        let mut remove = None;
+        ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+            for (n, obj) in self.metasprite.objs.iter_mut().enumerate() {
+                ui.push_id(n, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("#{n}"));
+                        ui.add(DragValue::new(&mut obj.x).prefix("x "));
+                        ui.add(DragValue::new(&mut obj.y).prefix("y "));
+                        ui.add(DragValue::new(&mut obj.tile).range(0..=511).hexadecimal(3, false, true).prefix("tile "));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.add(DragValue::new(&mut obj.palette).range(0..=7).prefix("pal "));
+                        ui.add(DragValue::new(&mut obj.priority).range(0..=3).prefix("pri "));
+                        ui.checkbox(&mut obj.h_flip, "H");
+                        ui.checkbox(&mut obj.v_flip, "V");
+                        let large = obj.size == ObjSize::Large;
+                        if ui.selectable_label(large, obj.size.to_string()).clicked() {
+                            obj.size = if large { ObjSize::Small } else { ObjSize::Large };
+                        }
+                        if ui.button("x").clicked() {
+                            remove = Some(n);
+                        }
+                    });
+                });
+            }
+        });
+        if let Some(n) = remove {
+            self.metasprite.objs.remove(n);
+        }
+        ui.separator();
+
+        let fields = &mut self.side_bar.sprite;
+        ui.horizontal(|ui| {
+            ui.label("Origin:");
+            ui.add(DragValue::new(&mut fields.origin.0).range(0..=511));
+            ui.add(DragValue::new(&mut fields.origin.1).range(0..=255));
+        });
+        ComboBox::from_label("Export As")
+            .selected_text(fields.export.to_string())
+            .show_ui(ui, |ui| {
+                for format in SpriteExport::ALL {
+                    ui.selectable_value(&mut fields.export, format, format.to_string());
+                }
+            }
+        );
+        if ui.button("Export Metasprite...").clicked() {
+            let format = fields.export;
+            let file = self.settings.dialog()
+                .add_filter(format.to_string(), &[format.extension()])
+                .save_file();
+            if let Some(file) = file {
+                self.settings.remember_dir(&file);
+                match self.metasprite.export(format, fields.origin).and_then(|data| Ok(fs::write(file, data)?)) {
+                    Ok(()) => {}
+                    Err(e) => self.toasts.error("Couldn't export metasprite", &e),
+                }
+            }
+        }
+    }
+
+    /// Draws the metasprite at 2x around its origin, using the canvas's tiles and palette. Color
+    /// 0 is transparent and earlier OBJs are drawn on top, same as on hardware.
+    fn metasprite_preview(&self, ui: &mut Ui) {
+        const SCALE: f32 = 2.0;
+        const SIZE: f32 = 128.0;
+        let (rect, _) = ui.allocate_exact_size(Vec2::splat(SIZE), Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::DARK_GRAY);
+        let origin = rect.center();
+        painter.line_segment([origin - Vec2::X * 4.0, origin + Vec2::X * 4.0], Stroke::new(1.0, Color32::GOLD));
+        painter.line_segment([origin - Vec2::Y * 4.0, origin + Vec2::Y * 4.0], Stroke::new(1.0, Color32::GOLD));
+
+        let grid = self.doc.canvas.grid();
+        let layout = self.side_bar.tile_order.layout(grid.width() / 8, grid.height() / 8);
+        for obj in self.metasprite.objs.iter().rev() {
+            let size = obj.size.pixels() as usize;
+            for y in 0..size {
+                for x in 0..size {
+                    let Some((cx, cy)) = obj.canvas_pixel(x, y, &layout) else {
+                        continue;
+                    };
+                    let idx = grid.get(cx, cy);
+                    if idx == 0 {
+                        continue;
+                    }
+                    let min = origin + Vec2::new(obj.x as f32 + x as f32, obj.y as f32 + y as f32) * SCALE;
+                    painter.rect_filled(Rect::from_min_size(min, Vec2::splat(SCALE)), 0.0, self.doc.canvas.palette.get_color(idx));
+                }
+            }
+        }
+    }
+}
+
+impl SnesPaintApp {
+    /// The active keybindings, and anything wrong with them.
+    fn keys_side_bar(&mut self, ui: &mut Ui) {
+        match Keymap::path() {
+            Some(path) => ui.label(format!("Keymap: {}", path.display())),
+            None => ui.label("No config directory, using the default keys"),
+        };
+        ui.horizontal(|ui| {
+            if ui.button("Reload").clicked() {
+                match Keymap::load() {
+                    Ok(keymap) => self.keymap = keymap,
+                    Err(e) => self.toasts.error("Couldn't load keymap", &e),
+                }
+            }
+            if ui.button("Write Keymap").on_hover_text("Save every binding, to edit by hand").clicked() {
+                match self.keymap.save() {
+                    Ok(path) => self.toasts.info(format!("Wrote {}", path.display())),
+                    Err(e) => self.toasts.error("Couldn't write keymap", &e),
+                }
+            }
+        });
+        for problem in &self.keymap.problems {
+            ui.colored_label(Color32::YELLOW, problem);
+        }
+        for (a, b) in self.keymap.conflicts() {
+            ui.colored_label(Color32::RED, format!("{a} and {b} share {}", keymap::format_shortcut(&self.keymap.get(a))));
+        }
+        ui.separator();
+        ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("Keys").striped(true).show(ui, |ui| {
+                for action in Action::ALL {
+                    ui.label(action.to_string());
+                    ui.monospace(keymap::format_shortcut(&self.keymap.get(action)));
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
+
+/// Edits a value kept in half pixels, showing it in pixels.
+fn half_pixels(value: &mut usize, max: usize) -> DragValue<'_> {
+    DragValue::new(value)
+        .range(0..=max)
+        .custom_formatter(|n, _| format!("{}", n / 2.0))
+        .custom_parser(|s| s.parse::<f64>().ok().map(|n| n * 2.0))
+}
+
+impl SnesPaintApp {
+    /// Asks where to save tile data and writes it with the selected compression.
+    fn save_tiles(&mut self, all_frames: bool) {
+        let order = self.side_bar.tile_order;
+        let serialized = if all_frames {
+            self.doc.canvas.serialize_frames(order)
+        } else {
+            self.doc.canvas.serialize_ordered(order)
+        };
+        let tiles = match serialized {
+            Ok((tiles, _)) => tiles,
+            Err(e) => {
+                self.toasts.error("Couldn't save tiles", &e);
+                return;
+            }
+        };
+        let file = self.settings.dialog().save_file();
+        if let Some(file) = file {
+            self.settings.remember_dir(&file);
+            if let Err(e) = self.write_tiles(&file, &tiles) {
+                self.toasts.error("Couldn't save tiles", &e);
+            }
+        }
+    }
+
+    fn write_tiles(&mut self, file: &Path, tiles: &[u8]) -> Result<(), Error> {
+        let compression = self.side_bar.compression;
+        let data = compress::compress_checked(tiles, compression)?;
+        if compression != Compression::None {
+            self.side_bar.last_compression = Some((tiles.len(), data.len()));
+        }
+        fs::write(file, data)?;
+        Ok(())
+    }
+
+    /// Writes the canvas out the way the File sidebar's settings say to.
+    fn export(&mut self, kind: ExportKind, file: &Path) -> Result<(), Error> {
+        let order = self.side_bar.tile_order;
+        let serialized = self.doc.canvas.serialize_ordered(order)?;
+        let name = file.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let bpp = self.doc.canvas.palette.bpp();
+        match kind {
+            ExportKind::Tiles => return self.write_tiles(file, &serialized.0),
+            ExportKind::Palette => fs::write(file, serialized.1)?,
+            ExportKind::Tilemap => fs::write(file, self.doc.canvas.serialize_tilemap(0, order))?,
+            ExportKind::Asm => {
+                let source = asm::write_asm(&name, &serialized.0, &serialized.1, bpp, self.side_bar.assembler);
+                fs::write(file, source)?;
+            }
+            ExportKind::Source => {
+                let tilemap = self.doc.canvas.serialize_tilemap(0, order);
+                let source = source::write_source(&name, &serialized.0, &serialized.1, &tilemap, bpp, self.side_bar.source_language);
+                fs::write(file, source)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns: what to tell the user once it's done.
+    fn run_command(&mut self, command: Command) -> Result<String, Error> {
+        match command {
+            Command::Write(path) => {
+                let path = path.or_else(|| self.doc.path.clone())
+                    .ok_or_else(|| Error::InvalidCommand("no file name, use :w path".to_owned()))?;
+                let message = format!("wrote {}", path.display());
+                self.save_project(path)?;
+                Ok(message)
+            }
+            Command::Edit(path) => {
+                let message = format!("opened {}", path.display());
+                self.open_project(path)?;
+                Ok(message)
+            }
+            Command::Size(width, height) => {
+                self.doc.canvas.set_size(width, height)?;
+                Ok(format!("canvas is {width}x{height}"))
+            }
+            Command::Bpp(bpp) => {
+                self.doc.canvas.set_bpp(bpp)?;
+                Ok(format!("palette is {}", self.doc.canvas.palette))
+            }
+            Command::Color(idx, value) => {
+                if idx >= self.doc.canvas.palette.size() {
+                    return Err(Error::InvalidCommand(format!("the palette only has {} colors", self.doc.canvas.palette.size())));
+                }
+                self.doc.canvas.palette.set_color(idx, serde::from_bgr555(value));
+                Ok(format!("color {idx} is {value:04x}"))
+            }
+            Command::Export(kind, path) => {
+                self.export(kind, &path)?;
+                Ok(format!("exported {kind} to {}", path.display()))
+            }
+        }
+    }
+
+    /// Where the cursor and pointer are, in pixels and in VRAM, and what's being painted with.
+    fn status_bar(&self, ui: &mut Ui) {
+        let canvas = &self.doc.canvas;
+        let (width, height) = (canvas.grid().width(), canvas.grid().height());
+        let bpp = canvas.palette.bpp();
+        let order = self.side_bar.tile_order;
+        let position = |(x, y): (usize, usize)| -> String {
+            match serde::vram_position(x, y, width, height, bpp, order) {
+                Some((tile, offset)) => format!("{x},{y} tile {tile} ${offset:04x}"),
+                None => format!("{x},{y} (not in VRAM)"),
+            }
+        };
+        ui.horizontal(|ui| {
+            ui.label(format!("cursor {}", position(canvas.cursor())));
+            ui.separator();
+            let pointer = ui.ctx().pointer_hover_pos().and_then(|pos| canvas.pixel_at(pos));
+            ui.label(format!("pointer {}", pointer.map_or("-".to_owned(), position)));
+            ui.separator();
+            let idx = canvas.color_idx;
+            ui.label(format!("color {idx} ${:04x}", serde::to_bgr555(canvas.palette.get_color(idx))));
+            ui.separator();
+            ui.label(format!("{width}x{height} {bpp}bpp"));
+        });
+    }
+
+    fn command_line(&mut self, ui: &mut Ui) {
+        if !self.command_line.open {
+            if let Some(message) = &self.command_line.message {
+                ui.label(message);
+            }
+            return;
+        }
+        // these have to be taken before the text field sees them
+        let (tab, up, down, escape) = ui.input_mut(|i| (
+            i.consume_key(Modifiers::NONE, Key::Tab),
+            i.consume_key(Modifiers::NONE, Key::ArrowUp),
+            i.consume_key(Modifiers::NONE, Key::ArrowDown),
+            i.consume_key(Modifiers::NONE, Key::Escape),
+        ));
+        if tab {
+            self.command_line.complete();
+        }
+        if up {
+            self.command_line.history_back();
+        }
+        if down {
+            self.command_line.history_forward();
+        }
+        if escape {
+            self.command_line.close();
+            return;
+        }
+
+        if let Some(message) = &self.command_line.message {
+            ui.weak(message);
+        }
+        let response = ui.horizontal(|ui| {
+            ui.label(":");
+            let field = TextEdit::singleline(&mut self.command_line.text)
+                .lock_focus(true)
+                .desired_width(f32::INFINITY);
+            let response = ui.add(field);
+            response.request_focus();
+            response
+        }).inner;
+        if response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+            let line = self.command_line.submit();
+            let result = Command::parse(&line).and_then(|command| self.run_command(command));
+            self.command_line.message = Some(match result {
+                Ok(message) => message,
+                Err(e) => format!("{e}"),
+            });
+        }
+    }
+
+    fn drawing_controls(&mut self, ui: &mut Ui) {
+        let canvas = &mut self.doc.canvas;
+        ComboBox::from_label("Mirror")
+            .selected_text(canvas.symmetry.to_string())
+            .show_ui(ui, |ui| {
+                for symmetry in Symmetry::ALL {
+                    ui.selectable_value(&mut canvas.symmetry, symmetry, symmetry.to_string());
+                }
+            }
+        );
+        if canvas.symmetry != Symmetry::None {
+            // the axis is kept in half pixels, but shown in pixels
+            let (mut x, mut y) = canvas.symmetry_axis();
+            let (width, height) = (canvas.grid().width() * 2, canvas.grid().height() * 2);
+            ui.horizontal(|ui| {
+                ui.label("Axis:");
+                ui.add(half_pixels(&mut x, width));
+                ui.add(half_pixels(&mut y, height));
+                if ui.button("Center").clicked() {
+                    canvas.symmetry_axis = None;
+                } else if (x, y) != canvas.symmetry_axis() {
+                    canvas.symmetry_axis = Some((x, y));
+                }
+            });
+        }
+
+        ComboBox::from_label("Brush")
+            .selected_text(canvas.brush.shape.to_string())
+            .show_ui(ui, |ui| {
+                for shape in BrushShape::ALL {
+                    ui.selectable_value(&mut canvas.brush.shape, shape, shape.to_string());
+                }
+            }
+        );
+        match canvas.brush.shape {
+            BrushShape::Custom => {
+                match &canvas.brush.custom {
+                    Some(custom) => ui.label(format!("{}x{} brush", custom.width(), custom.height())),
+                    None => ui.label("Select with ctrl+drag, then capture"),
+                };
+                ui.checkbox(&mut canvas.brush.transparent, "Color 0 is transparent");
+            }
+            _ => {
+                ui.add(Slider::new(&mut canvas.brush.size, 1..=16).text("Size"));
+            }
+        }
+        ui.add_enabled(
+            canvas.brush.shape != BrushShape::Custom && canvas.brush.size == 1,
+            Checkbox::new(&mut canvas.pixel_perfect, "Pixel perfect strokes"),
+        );
+        if ui.add_enabled(canvas.selection.is_some(), Button::new("Capture Brush (ctrl+b)")).clicked() {
+            canvas.capture_brush();
+        }
+
+        ComboBox::from_label("Dither")
+            .selected_text(canvas.dither.to_string())
+            .show_ui(ui, |ui| {
+                for dither in Dither::ALL {
+                    ui.selectable_value(&mut canvas.dither, dither, dither.to_string());
+                }
+            }
+        );
+        if canvas.dither != Dither::None {
+            ui.label(format!("Secondary color: {} (right click)", canvas.secondary_idx));
+        }
+        if canvas.dither == Dither::Bayer {
+            ui.add(Slider::new(&mut canvas.dither_density, 1..=15).text("Density /16"));
+        }
+    }
+
+    fn frame_controls(&mut self, ui: &mut Ui) {
+        let frames = self.doc.canvas.frames().len();
+        ui.horizontal(|ui| {
+            if ui.button("<").clicked() {
+                self.doc.canvas.set_frame((self.doc.canvas.frame() + frames - 1) % frames);
+            }
+            ui.label(format!("Frame {}/{}", self.doc.canvas.frame() + 1, frames));
+            if ui.button(">").clicked() {
+                self.doc.canvas.set_frame((self.doc.canvas.frame() + 1) % frames);
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Add").clicked() {
+                self.doc.canvas.add_frame();
+            }
+            if ui.button("Duplicate").clicked() {
+                self.doc.canvas.duplicate_frame();
+            }
+            if ui.add_enabled(frames > 1, Button::new("Delete")).clicked() {
+                self.doc.canvas.delete_frame();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Duration:");
+            ui.add(DragValue::new(self.doc.canvas.frame_duration_mut()).range(1..=255).suffix("/60 s"));
+        });
+
+        let onion_skin = &mut self.doc.canvas.onion_skin;
+        ui.checkbox(&mut onion_skin.enabled, "Onion skin (o)");
+        ui.add_enabled_ui(onion_skin.enabled, |ui| {
+            ui.add(Slider::new(&mut onion_skin.opacity, 0.05..=1.0).text("Opacity"));
+            ui.horizontal(|ui| {
+                ui.label("Previous");
+                ui.color_edit_button_srgba(&mut onion_skin.previous_tint);
+                ui.label("Next");
+                ui.color_edit_button_srgba(&mut onion_skin.next_tint);
+            });
+        });
+    }
+}
+
 impl App for SnesPaintApp {
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
+        let colon = ctx.input(|i| i.events.iter().any(|e| matches!(e, Event::Text(t) if t == ":")));
+        if colon && !self.command_line.open && !ctx.wants_keyboard_input() {
+            self.command_line.open();
+        }
+        self.document_keys(ctx);
+        TopBottomPanel::top(Id::new("Tabs")).show(ctx, |ui| {
+            self.tab_bar(ui);
+        });
+        TopBottomPanel::bottom(Id::new("CommandLine")).show(ctx, |ui| {
+            self.command_line(ui);
+        });
+        TopBottomPanel::bottom(Id::new("StatusBar")).show(ctx, |ui| {
+            self.status_bar(ui);
+        });
         SidePanel::right(Id::new("SidePanel")).min_width(200.0).max_width(300.0).show(ctx, |ui| {
             ui.separator();
             // display menu bar for selecting functions
@@ -75,8 +1343,8 @@ impl App for SnesPaintApp {
                     SideBarType::File,
                     "File"
                 ).interact(Sense::hover());
-                if let Some(_) = file_hover.hover_pos() {
-                    file_hover.show_tooltip_text("alt+f");
+                if file_hover.hover_pos().is_some() {
+                    file_hover.show_tooltip_text(keymap::format_shortcut(&self.keymap.get(Action::SidebarFile)));
                 }
 
                 let canvas_hover = ui.selectable_value(
@@ -84,18 +1352,66 @@ impl App for SnesPaintApp {
                     SideBarType::Canvas,
                     "Canvas"
                 ).interact(Sense::hover());
-                if let Some(_) = canvas_hover.hover_pos() {
-                    canvas_hover.show_tooltip_text("alt+c");
+                if canvas_hover.hover_pos().is_some() {
+                    canvas_hover.show_tooltip_text(keymap::format_shortcut(&self.keymap.get(Action::SidebarCanvas)));
+                }
+
+                let rom_hover = ui.selectable_value(
+                    &mut self.side_bar.side_bar_type,
+                    SideBarType::Rom,
+                    "ROM"
+                ).interact(Sense::hover());
+                if rom_hover.hover_pos().is_some() {
+                    rom_hover.show_tooltip_text(keymap::format_shortcut(&self.keymap.get(Action::SidebarRom)));
+                }
+
+                let vram_hover = ui.selectable_value(
+                    &mut self.side_bar.side_bar_type,
+                    SideBarType::Vram,
+                    "VRAM"
+                ).interact(Sense::hover());
+                if vram_hover.hover_pos().is_some() {
+                    vram_hover.show_tooltip_text(keymap::format_shortcut(&self.keymap.get(Action::SidebarVram)));
+                }
+
+                let sprite_hover = ui.selectable_value(
+                    &mut self.side_bar.side_bar_type,
+                    SideBarType::Sprite,
+                    "Sprite"
+                ).interact(Sense::hover());
+                if sprite_hover.hover_pos().is_some() {
+                    sprite_hover.show_tooltip_text(keymap::format_shortcut(&self.keymap.get(Action::SidebarSprite)));
+                }
+
+                let keys_hover = ui.selectable_value(
+                    &mut self.side_bar.side_bar_type,
+                    SideBarType::Keys,
+                    "Keys"
+                ).interact(Sense::hover());
+                if keys_hover.hover_pos().is_some() {
+                    keys_hover.show_tooltip_text(keymap::format_shortcut(&self.keymap.get(Action::SidebarKeys)));
                 }
             });
             ui.separator();
 
-            if ui.input_mut(|mut i| i.consume_shortcut(&shortcut::SIDEBAR_FILE)) {
+            if ui.input_mut(|i| i.consume_shortcut(&self.keymap.get(Action::SidebarFile))) {
                 self.side_bar.side_bar_type = SideBarType::File;
             }
-            if ui.input_mut(|mut i| i.consume_shortcut(&shortcut::SIDEBAR_CANVAS)) {
+            if ui.input_mut(|i| i.consume_shortcut(&self.keymap.get(Action::SidebarCanvas))) {
                 self.side_bar.side_bar_type = SideBarType::Canvas;
             }
+            if ui.input_mut(|i| i.consume_shortcut(&self.keymap.get(Action::SidebarRom))) {
+                self.side_bar.side_bar_type = SideBarType::Rom;
+            }
+            if ui.input_mut(|i| i.consume_shortcut(&self.keymap.get(Action::SidebarVram))) {
+                self.side_bar.side_bar_type = SideBarType::Vram;
+            }
+            if ui.input_mut(|i| i.consume_shortcut(&self.keymap.get(Action::SidebarSprite))) {
+                self.side_bar.side_bar_type = SideBarType::Sprite;
+            }
+            if ui.input_mut(|i| i.consume_shortcut(&self.keymap.get(Action::SidebarKeys))) {
+                self.side_bar.side_bar_type = SideBarType::Keys;
+            }
 
             // depending on selected menu bar, select certain functionality
             match self.side_bar.side_bar_type {
@@ -103,31 +1419,32 @@ impl App for SnesPaintApp {
                     // field for changing grid size
                     ui.horizontal(|ui| {
                         ui.label("Size:");
-                        let mut width = &mut self.side_bar.canvas_width_field;
+                        let width = &mut self.side_bar.canvas_width_field;
                         ui.add(TextEdit::singleline(width).desired_width(25.0));
                         let w_set = width.clone();
 
                         ui.label("x");
 
-                        let mut height = &mut self.side_bar.canvas_height_field;
+                        let height = &mut self.side_bar.canvas_height_field;
                         ui.add(TextEdit::singleline(height).desired_width(25.0));
                         let h_set = height.clone();
 
                         if ui.button("Apply").clicked() {
-                            match self.canvas.set_size(
-                                usize::from_str_radix(w_set.as_str(), 10).unwrap(),
-                                usize::from_str_radix(h_set.as_str(), 10).unwrap(),
-                            ) {
-                                Ok(_) => {}
-                                Err(_) => {println!("Change this to label!!!")}
-                            };
+                            match (w_set.trim().parse::<usize>(), h_set.trim().parse::<usize>()) {
+                                (Ok(width), Ok(height)) => {
+                                    if let Err(e) = self.doc.canvas.set_size(width, height) {
+                                        self.toasts.error("Couldn't resize canvas", &e);
+                                    }
+                                }
+                                _ => self.toasts.error("Couldn't resize canvas", &Error::Parse("size", format!("{w_set}x{h_set}"))),
+                            }
                         }
                     });
                     // field for changing palette type
                     ui.horizontal(|ui| {
-                        let mut current_bpp = self.canvas.palette.bpp();
+                        let mut current_bpp = self.doc.canvas.palette.bpp();
                         ComboBox::from_label("Palette Size:")
-                            .selected_text(self.canvas.palette.to_string())
+                            .selected_text(self.doc.canvas.palette.to_string())
                             .show_ui(ui, |ui| {
                                 ui.selectable_value(&mut current_bpp, 2, "2 BPP (4 colors)");
                                 ui.selectable_value(&mut current_bpp, 3, "3 BPP (8 colors)");
@@ -136,53 +1453,259 @@ impl App for SnesPaintApp {
                             }
                         );
 
-                        if current_bpp != self.canvas.palette.bpp() {
-                            self.canvas.palette.set_bpp(current_bpp);
+                        if current_bpp != self.doc.canvas.palette.bpp() {
+                            if let Err(e) = self.doc.canvas.set_bpp(current_bpp) {
+                                self.toasts.error("Couldn't change the palette size", &e);
+                            }
                         }
                     });
+                    ui.add(Slider::new(&mut self.doc.canvas.pixel_width, 1..=64).text("Zoom"));
+                    ui.separator();
+                    self.drawing_controls(ui);
+                    ui.separator();
+                    self.frame_controls(ui);
                 },
                 SideBarType::File => {
                     // Save file
-                    if ui.button("Test BPPS Stuff!").clicked() {
-                        let serialized = self.canvas.serialize();
-                        println!("Canvas VRAM data:");
-                        for c in serialized.0.chunks(2) {
-                            println!("{:b} {:b}", c[0], c[1]);
+                    ComboBox::from_label("Compression")
+                        .selected_text(self.side_bar.compression.to_string())
+                        .show_ui(ui, |ui| {
+                            for compression in Compression::ALL {
+                                ui.selectable_value(&mut self.side_bar.compression, compression, compression.to_string());
+                            }
                         }
-                        println!("Palette data:");
-                        for c in serialized.1.chunks(2) {
-                            println!("{:X} {:X}", c[0], c[1]);
+                    );
+                    ComboBox::from_label("Tile Order")
+                        .selected_text(self.side_bar.tile_order.to_string())
+                        .show_ui(ui, |ui| {
+                            for order in TileOrder::ALL {
+                                ui.selectable_value(&mut self.side_bar.tile_order, order, order.to_string());
+                            }
                         }
-                    }
+                    );
                     if ui.button("Save...").clicked() {
-                        let serialized = self.canvas.serialize();
-                        let file = rfd::FileDialog::new().save_file();
+                        self.save_tiles(false);
+                    }
+                    if self.doc.canvas.frames().len() > 1 && ui.button("Save All Frames...").clicked() {
+                        self.save_tiles(true);
+                    }
+                    if let Some((original, compressed)) = self.side_bar.last_compression {
+                        ui.label(format!(
+                            "Last save: {original} -> {compressed} bytes ({:.1}%)",
+                            compress::ratio(original, compressed),
+                        ));
+                    }
+                    if ui.button("Save Palette...").clicked() {
+                        let file = self.settings.dialog().save_file();
                         if let Some(file) = file {
-                            fs::write(file, serialized.0).unwrap();
+                            self.settings.remember_dir(&file);
+                            if let Err(e) = self.export(ExportKind::Palette, &file) {
+                                self.toasts.error("Couldn't save palette", &e);
+                            }
                         }
                     }
-                    if ui.button("Save Palette...").clicked() {
-                        let serialized = self.canvas.serialize();
-                        let file = rfd::FileDialog::new().save_file();
+                    ui.separator();
+                    ComboBox::from_label("Assembler")
+                        .selected_text(self.side_bar.assembler.to_string())
+                        .show_ui(ui, |ui| {
+                            for assembler in Assembler::ALL {
+                                ui.selectable_value(&mut self.side_bar.assembler, assembler, assembler.to_string());
+                            }
+                        }
+                    );
+                    if ui.button("Export Assembly...").clicked() {
+                        let assembler = self.side_bar.assembler;
+                        let file = self.settings.dialog()
+                            .add_filter(assembler.to_string(), &[assembler.extension()])
+                            .save_file();
+                        if let Some(file) = file {
+                            self.settings.remember_dir(&file);
+                            if let Err(e) = self.export(ExportKind::Asm, &file) {
+                                self.toasts.error("Couldn't export assembly", &e);
+                            }
+                        }
+                    }
+                    ComboBox::from_label("Language")
+                        .selected_text(self.side_bar.source_language.to_string())
+                        .show_ui(ui, |ui| {
+                            for lang in SourceLanguage::ALL {
+                                ui.selectable_value(&mut self.side_bar.source_language, lang, lang.to_string());
+                            }
+                        }
+                    );
+                    if ui.button("Export Source...").clicked() {
+                        let lang = self.side_bar.source_language;
+                        let file = self.settings.dialog()
+                            .add_filter(lang.to_string(), &[lang.extension()])
+                            .save_file();
+                        if let Some(file) = file {
+                            self.settings.remember_dir(&file);
+                            if let Err(e) = self.export(ExportKind::Source, &file) {
+                                self.toasts.error("Couldn't export source", &e);
+                            }
+                        }
+                    }
+                    ui.separator();
+                    ComboBox::from_label("Palette Format")
+                        .selected_text(self.side_bar.palette_format.to_string())
+                        .show_ui(ui, |ui| {
+                            for format in PaletteFormat::ALL {
+                                ui.selectable_value(&mut self.side_bar.palette_format, format, format.to_string());
+                            }
+                        }
+                    );
+                    if ui.button("Export Palette...").clicked() {
+                        let format = self.side_bar.palette_format;
+                        let file = self.settings.dialog()
+                            .add_filter(format.to_string(), &[format.extension()])
+                            .save_file();
+                        if let Some(file) = file {
+                            self.settings.remember_dir(&file);
+                            if let Err(e) = palette::save(&file, &self.doc.canvas.palette, format) {
+                                self.toasts.error("Couldn't export palette", &e);
+                            }
+                        }
+                    }
+                    ui.checkbox(&mut self.side_bar.keep_palette_bpp, "Keep palette size on import");
+                    if ui.button("Import Palette...").clicked() {
+                        let file = self.settings.dialog()
+                            .add_filter("Palettes", &["gpl", "pal", "act", "hex", "txt", "cgr", "bin"])
+                            .pick_file();
+                        if let Some(file) = file {
+                            self.settings.remember_dir(&file);
+                            match palette::load(&file) {
+                                Ok(colors) if self.side_bar.keep_palette_bpp => {
+                                    self.doc.canvas.palette.fit_colors(&colors);
+                                }
+                                Ok(colors) => {
+                                    self.doc.canvas.palette = Palette::from_colors(&colors);
+                                    self.doc.canvas.color_idx = Ord::min(self.doc.canvas.color_idx, self.doc.canvas.palette.size() - 1);
+                                }
+                                Err(e) => self.toasts.error("Couldn't import palette", &e),
+                            }
+                        }
+                    }
+                    ui.separator();
+                    if ui.button("Open...").clicked() {
+                        let file = self.settings.dialog()
+                            .add_filter("Projects and images", &[project::EXTENSION, "png"])
+                            .pick_file();
                         if let Some(file) = file {
-                            fs::write(file, serialized.1).unwrap();
+                            self.settings.remember_dir(&file);
+                            let name = file.display().to_string();
+                            if let Err(e) = self.open_project(file) {
+                                self.toasts.error(&format!("Couldn't open {name}"), &e);
+                            }
+                        }
+                    }
+                    if ui.button("Save Project...").clicked() {
+                        let file = self.settings.dialog()
+                            .add_filter("Project", &[project::EXTENSION])
+                            .save_file();
+                        if let Some(file) = file {
+                            self.settings.remember_dir(&file);
+                            let name = file.display().to_string();
+                            match self.save_project(file) {
+                                Ok(()) => self.toasts.info(format!("Saved {name}")),
+                                Err(e) => self.toasts.error("Couldn't save project", &e),
+                            }
+                        }
+                    }
+                    if !self.settings.recent.is_empty() {
+                        ui.separator();
+                        ui.label("Recent:");
+                        let mut open = None;
+                        for file in &self.settings.recent {
+                            let name = file.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                            if ui.button(name).on_hover_text(file.display().to_string()).clicked() {
+                                open = Some(file.clone());
+                            }
+                        }
+                        if let Some(file) = open {
+                            let name = file.display().to_string();
+                            if let Err(e) = self.open_project(file) {
+                                self.toasts.error(&format!("Couldn't open {name}"), &e);
+                            }
                         }
                     }
-                    // Load file
                 }
+                SideBarType::Rom => self.rom_side_bar(ui),
+                SideBarType::Vram => self.vram_side_bar(ui),
+                SideBarType::Sprite => self.sprite_side_bar(ui),
+                SideBarType::Keys => self.keys_side_bar(ui),
                 _ => {}
             }
         });
         CentralPanel::default().show(ctx, |ui| {
             ui.heading("Hello World!");
+            ui.horizontal(|ui| {
+                let history = &mut self.doc.history;
+                let undo = keymap::format_shortcut(&self.keymap.get(Action::Undo));
+                if ui.add_enabled(history.can_undo(), Button::new("Undo")).on_hover_text(undo).clicked() {
+                    history.undo(&mut self.doc.canvas);
+                }
+                let redo = keymap::format_shortcut(&self.keymap.get(Action::Redo));
+                if ui.add_enabled(history.can_redo(), Button::new("Redo")).on_hover_text(redo).clicked() {
+                    history.redo(&mut self.doc.canvas);
+                }
+                ui.separator();
+                ui.label(format!("-- {} -- {}", self.vim.mode(), self.vim.pending()));
+                if let Some(register) = self.vim.register() {
+                    ui.weak(format!("yanked {}x{}", register.width(), register.height()));
+                }
+            });
             ui.separator();
+            self.vim.update(&mut self.doc.canvas, ui, &self.keymap);
             ui.horizontal(|ui| {
-                self.canvas.update(ui);
-                self.canvas.render(ui);
-                let idx = self.canvas.color_idx;
-                ui.color_edit_button_srgba(self.canvas.get_palette_mut().get_color_mut(idx));
+                self.doc.canvas.update(ui, &self.keymap);
+                self.doc.canvas.render(ui);
+                let idx = self.doc.canvas.color_idx;
+                ui.color_edit_button_srgba(self.doc.canvas.get_palette_mut().get_color_mut(idx));
             });
             ui.separator();
+            if self.doc.canvas.frames().len() > 1 {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.playing, "Preview");
+                    if self.playing {
+                        // durations are in SNES frames, so count time at 60 per second
+                        let ticks = (ui.input(|i| i.time) * 60.0) as u64;
+                        self.doc.canvas.render_preview(ui, self.doc.canvas.frame_at(ticks));
+                        ui.ctx().request_repaint();
+                    }
+                });
+            }
         });
+        // a stroke is one undo step, so wait until it's finished. Same for settings, so dragging
+        // around a color picker doesn't rewrite the file every frame
+        if !ctx.input(|i| i.pointer.any_down()) {
+            self.doc.history.record(&self.doc.canvas);
+            self.store_settings();
+        }
+
+        self.recovery_window(ctx);
+        self.close_window(ctx);
+        self.quit_window(ctx);
+        self.toasts.show(ctx);
+        // don't write over a crashed session's work before the user's decided about it
+        if self.recovered.is_none() {
+            let time = ctx.input(|i| i.time);
+            let mut documents: Vec<&Document> = self.others.iter().collect();
+            documents.insert(self.active, &self.doc);
+            if let Err(e) = self.autosave.update(time, &documents) {
+                self.toasts.error("Couldn't autosave", &e);
+            }
+        }
+        if ctx.input(|i| i.viewport().close_requested()) {
+            let dirty = (0..self.tab_count()).any(|n| self.tab(n).history.is_dirty());
+            if dirty && !self.quit_confirmed {
+                ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+                self.quitting = true;
+            } else if self.recovered.is_none() {
+                // an autosave nobody's decided about yet gets offered again next time
+                if let Err(e) = self.autosave.finish() {
+                    self.toasts.error("Couldn't clean up autosave", &e);
+                }
+            }
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,344 @@
+//! Headless mode, for Makefiles and build servers: `snes-paint <command> ...` converts files
+//! without ever opening a window.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::paint::{Canvas, Grid, Palette};
+use crate::serde::asm::{self, Assembler};
+use crate::serde::compress::{self, Compression};
+use crate::serde::palette::{self, PaletteFormat};
+use crate::serde::source::{self, SourceLanguage};
+use crate::serde::{bitmap, project, tile_size, TileOrder};
+use crate::Error;
+
+mod manifest;
+mod watch;
+
+const USAGE: &str = "\
+usage: snes-paint [<command> <input> [options]]
+
+With no command, opens the editor.
+
+commands:
+  convert <input>   write tiles, palette and tilemap for a PNG or project file
+  palette <input>   write just the palette of a PNG, project or palette file
+  info <input>      print size, bpp and color count
+  build <manifest>  convert every asset listed in a manifest file
+  watch <input>     like convert (or build, for a .toml manifest), then again every time
+                    an input changes; stop with ctrl+c
+  help              print this message
+
+options:
+  -o, --output <path>      output path (convert: prefix for .bin/.pal/.map)
+  -f, --format <format>    convert: bin, ca65, asar, wla-dx, c, rust (default bin)
+                           palette: gpl, jasc, act, hex, bgr555 (default bgr555)
+  -b, --bpp <bpp>          bpp to convert at (2, 3, 4 or 8); defaults to the smallest that fits
+  -p, --palette <path>     map PNG colors onto this palette instead of building one
+  -s, --palette-slot <n>   palette number written into tilemap entries (0-7)
+  -t, --order <order>      tile order in VRAM: linear (default) or sprite16
+  -c, --compress <format>  compress bin tiles and tilemap: none (default), rle, lz2 or lz3";
+
+/// What `convert` writes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum OutputFormat {
+    Bin,
+    Asm(Assembler),
+    Source(SourceLanguage),
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Result<OutputFormat, Error> {
+        match name.to_ascii_lowercase().as_str() {
+            "bin" => Ok(OutputFormat::Bin),
+            "ca65" => Ok(OutputFormat::Asm(Assembler::Ca65)),
+            "asar" => Ok(OutputFormat::Asm(Assembler::Asar)),
+            "wla" | "wla-dx" | "wladx" => Ok(OutputFormat::Asm(Assembler::WlaDx)),
+            "c" | "h" => Ok(OutputFormat::Source(SourceLanguage::C)),
+            "rust" | "rs" => Ok(OutputFormat::Source(SourceLanguage::Rust)),
+            _ => Err(Error::Usage(format!("unknown output format {name:?}"))),
+        }
+    }
+}
+
+fn parse_palette_format(name: &str) -> Result<PaletteFormat, Error> {
+    match name.to_ascii_lowercase().as_str() {
+        "gpl" => Ok(PaletteFormat::Gpl),
+        "jasc" | "aseprite" => Ok(PaletteFormat::Jasc),
+        "act" => Ok(PaletteFormat::Act),
+        "hex" => Ok(PaletteFormat::Hex),
+        "bgr555" | "bin" | "snes" => Ok(PaletteFormat::Bgr555),
+        _ => Err(Error::Usage(format!("unknown palette format {name:?}"))),
+    }
+}
+
+#[derive(Clone, Default)]
+struct Options {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    format: Option<String>,
+    bpp: Option<usize>,
+    palette: Option<PathBuf>,
+    palette_slot: u8,
+    order: TileOrder,
+    compression: Compression,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, Error> {
+        let mut options = Options::default();
+        let mut input = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| Error::Usage(format!("{arg} needs a value")));
+            match arg.as_str() {
+                "-o" | "--output" => options.output = Some(PathBuf::from(value()?)),
+                "-f" | "--format" => options.format = Some(value()?.clone()),
+                "-b" | "--bpp" => options.bpp = Some(parse_bpp(value()?)?),
+                "-p" | "--palette" => options.palette = Some(PathBuf::from(value()?)),
+                "-s" | "--palette-slot" => options.palette_slot = parse_palette_slot(value()?)?,
+                "-t" | "--order" => options.order = parse_order(value()?)?,
+                "-c" | "--compress" => options.compression = parse_compression(value()?)?,
+                _ if arg.starts_with('-') => return Err(Error::Usage(format!("unknown option {arg}"))),
+                _ if input.is_none() => input = Some(PathBuf::from(arg)),
+                _ => return Err(Error::Usage(format!("unexpected argument {arg:?}"))),
+            }
+        }
+        options.input = input.ok_or_else(|| Error::Usage("missing input file".to_owned()))?;
+        Ok(options)
+    }
+}
+
+pub(crate) fn parse_bpp(value: &str) -> Result<usize, Error> {
+    match value.parse() {
+        Ok(bpp @ (2 | 3 | 4 | 8)) => Ok(bpp),
+        _ => Err(Error::Usage(format!("bpp must be 2, 3, 4 or 8, not {value:?}"))),
+    }
+}
+
+pub(crate) fn parse_palette_slot(value: &str) -> Result<u8, Error> {
+    match value.parse() {
+        Ok(slot @ 0..=7) => Ok(slot),
+        _ => Err(Error::Usage(format!("palette slot must be 0-7, not {value:?}"))),
+    }
+}
+
+pub(crate) fn parse_order(value: &str) -> Result<TileOrder, Error> {
+    TileOrder::parse(value).ok_or_else(|| Error::Usage(format!("tile order must be linear or sprite16, not {value:?}")))
+}
+
+pub(crate) fn parse_compression(value: &str) -> Result<Compression, Error> {
+    Compression::parse(value).ok_or_else(|| Error::Usage(format!("compression must be none, rle, lz2 or lz3, not {value:?}")))
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let Some((command, args)) = args.split_first() else {
+        return Err(Error::Usage(USAGE.to_owned()));
+    };
+    match command.as_str() {
+        "convert" => convert(&Options::parse(args)?),
+        "palette" => write_palette(&Options::parse(args)?),
+        "info" => info(&Options::parse(args)?),
+        "build" => match args {
+            [path] => manifest::build(Path::new(path)),
+            _ => Err(Error::Usage("build takes exactly one manifest file".to_owned())),
+        },
+        "watch" => match args {
+            [path] if path.ends_with(".toml") => watch::watch_manifest(Path::new(path)),
+            _ => watch::watch_convert(Options::parse(args)?),
+        },
+        "help" | "-h" | "--help" => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => Err(Error::Usage(format!("unknown command {command:?}\n\n{USAGE}"))),
+    }
+}
+
+/// Opens a PNG or project file, optionally forcing its colors onto an existing palette and its
+/// bpp to a given mode.
+pub(crate) fn load_input(input: &Path, palette_path: Option<&Path>, bpp: Option<usize>) -> Result<Canvas, Error> {
+    let palette = match palette_path {
+        Some(path) => Some(Palette::from_colors(&palette::load(path)?)),
+        None => None,
+    };
+    let is_png = input.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    let mut canvas = if is_png {
+        let (grid, palette) = bitmap::read_png(input, palette.as_ref())?;
+        Canvas::from_parts(Box::new(grid), palette)
+    } else {
+        let mut canvas = project::open(input)?;
+        if let Some(palette) = palette {
+            canvas.palette = palette;
+        }
+        canvas
+    };
+
+    if let Some(bpp) = bpp {
+        // every frame gets exported, not just the one being edited
+        let used = canvas.frames().iter().map(|frame| max_index(frame.grid.as_ref())).max().unwrap_or(0) + 1;
+        if used > 1 << bpp {
+            return Err(Error::InvalidImage(format!(
+                "{}: uses {used} colors, which won't fit in {bpp}bpp",
+                input.display(),
+            )));
+        }
+        canvas.set_bpp(bpp)?;
+    }
+    Ok(canvas)
+}
+
+fn max_index(grid: &dyn Grid<usize>) -> usize {
+    let mut max = 0;
+    for x in 0..grid.width() {
+        for y in 0..grid.height() {
+            max = Ord::max(max, grid.get(x, y));
+        }
+    }
+    max
+}
+
+/// Where converted data goes. Source formats put everything in `tiles`, so the other two are
+/// ignored for them.
+pub(crate) struct Outputs {
+    pub tiles: PathBuf,
+    pub palette: Option<PathBuf>,
+    pub tilemap: Option<PathBuf>,
+}
+
+impl Outputs {
+    /// `prefix.bin`, `prefix.pal` and `prefix.map`, or `prefix.<ext>` for source formats.
+    pub fn from_prefix(prefix: &Path, format: OutputFormat) -> Outputs {
+        let tiles = match format {
+            OutputFormat::Bin => prefix.with_extension("bin"),
+            OutputFormat::Asm(asm) => prefix.with_extension(asm.extension()),
+            OutputFormat::Source(lang) => prefix.with_extension(lang.extension()),
+        };
+        Outputs {
+            tiles,
+            palette: Some(prefix.with_extension("pal")),
+            tilemap: Some(prefix.with_extension("map")),
+        }
+    }
+}
+
+/// Everything about a conversion besides where it reads from and writes to.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Conversion {
+    pub format: OutputFormat,
+    pub order: TileOrder,
+    pub palette_slot: u8,
+    pub compression: Compression,
+}
+
+pub(crate) fn write_outputs(canvas: &Canvas, outputs: &Outputs, conversion: &Conversion) -> Result<(), Error> {
+    let grid = canvas.grid();
+    let block = conversion.order.block_size();
+    if grid.width() % block != 0 || grid.height() % block != 0 {
+        return Err(Error::InvalidImage(format!(
+            "{}x{} isn't a whole number of {block}x{block} blocks for {}",
+            grid.width(),
+            grid.height(),
+            conversion.order,
+        )));
+    }
+    if conversion.compression != Compression::None && conversion.format != OutputFormat::Bin {
+        return Err(Error::Usage("compression only applies to bin output".to_owned()));
+    }
+
+    // animations export every frame back to back; the tilemap is for the first one, and the
+    // rest are the same layout offset by a frame's worth of tiles
+    let (v_ram, pal) = canvas.serialize_frames(conversion.order)?;
+    let tilemap = canvas.serialize_tilemap(conversion.palette_slot, conversion.order);
+    let bpp = canvas.palette.bpp();
+    let name = outputs.tiles.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+
+    for path in [Some(&outputs.tiles), outputs.palette.as_ref(), outputs.tilemap.as_ref()].into_iter().flatten() {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+    }
+
+    match conversion.format {
+        OutputFormat::Bin => {
+            fs::write(&outputs.tiles, compressed(&v_ram, conversion.compression, &outputs.tiles)?)?;
+            if let Some(path) = &outputs.palette {
+                fs::write(path, pal)?;
+            }
+            if let Some(path) = &outputs.tilemap {
+                fs::write(path, compressed(&tilemap, conversion.compression, path)?)?;
+            }
+        }
+        OutputFormat::Asm(assembler) => {
+            fs::write(&outputs.tiles, asm::write_asm(&name, &v_ram, &pal, bpp, assembler))?;
+        }
+        OutputFormat::Source(lang) => {
+            fs::write(&outputs.tiles, source::write_source(&name, &v_ram, &pal, &tilemap, bpp, lang))?;
+        }
+    }
+    Ok(())
+}
+
+/// Compresses data on its way to `path`, printing how much it saved.
+fn compressed(data: &[u8], compression: Compression, path: &Path) -> Result<Vec<u8>, Error> {
+    if compression == Compression::None {
+        return Ok(data.to_vec());
+    }
+    let out = compress::compress_checked(data, compression)?;
+    println!(
+        "  {}: {} -> {} bytes with {compression} ({:.1}%)",
+        path.display(),
+        data.len(),
+        out.len(),
+        compress::ratio(data.len(), out.len()),
+    );
+    Ok(out)
+}
+
+fn convert(options: &Options) -> Result<(), Error> {
+    let canvas = load_input(&options.input, options.palette.as_deref(), options.bpp)?;
+    let format = match &options.format {
+        Some(format) => OutputFormat::parse(format)?,
+        None => OutputFormat::Bin,
+    };
+    let prefix = options.output.clone().unwrap_or_else(|| options.input.clone());
+    let conversion = Conversion {
+        format,
+        order: options.order,
+        palette_slot: options.palette_slot,
+        compression: options.compression,
+    };
+    write_outputs(&canvas, &Outputs::from_prefix(&prefix, format), &conversion)
+}
+
+fn write_palette(options: &Options) -> Result<(), Error> {
+    let format = match &options.format {
+        Some(format) => parse_palette_format(format)?,
+        None => PaletteFormat::Bgr555,
+    };
+    // palette files can be converted straight across; images and projects go through the canvas
+    let is_canvas = options.input.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png") || ext.eq_ignore_ascii_case(project::EXTENSION));
+    let palette = if is_canvas {
+        load_input(&options.input, None, options.bpp)?.palette
+    } else {
+        Palette::from_colors(&palette::load(&options.input)?)
+    };
+    let output = options.output.clone()
+        .unwrap_or_else(|| options.input.with_extension(format.extension()));
+    if output == options.input {
+        return Err(Error::Usage(format!("refusing to overwrite {}; pass -o", output.display())));
+    }
+    palette::save(&output, &palette, format)
+}
+
+fn info(options: &Options) -> Result<(), Error> {
+    let canvas = load_input(&options.input, options.palette.as_deref(), options.bpp)?;
+    let grid = canvas.grid();
+    let bpp = canvas.palette.bpp();
+    let tiles = (grid.width() / 8) * (grid.height() / 8);
+    println!("{}", options.input.display());
+    println!("  size:    {}x{} ({} tiles)", grid.width(), grid.height(), tiles);
+    println!("  palette: {} ({} colors used)", canvas.palette, max_index(grid) + 1);
+    println!("  vram:    {} bytes", tiles * tile_size(bpp));
+    Ok(())
+}
@@ -0,0 +1,229 @@
+//! Manifest files: a TOML list of assets to convert in one go with `snes-paint build`.
+//!
+//! ```toml
+//! # applies to every asset unless the asset says otherwise
+//! [defaults]
+//! bpp = 4
+//! out_dir = "build/gfx"
+//!
+//! [[asset]]
+//! input = "sprites/hero.png"
+//! order = "sprite16"          # or "linear"
+//! palette = "hero.gpl"        # map colors onto this palette instead of building one
+//! palette_slot = 2            # written into tilemap entries
+//! format = "bin"              # or ca65, asar, wla-dx, c, rust
+//! compress = "lz2"            # bin only: none, rle, lz2 or lz3
+//! tiles = "build/hero.chr"    # defaults to <out_dir>/<input name>.bin
+//! cgram = "build/hero.cgr"    # defaults to <out_dir>/<input name>.pal; false to skip
+//! tilemap = false             # defaults to <out_dir>/<input name>.map; false to skip
+//! ```
+//!
+//! Relative paths are relative to the manifest. `output = "build/hero"` sets all three outputs
+//! from one prefix.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+use crate::cli::{self, Conversion, OutputFormat, Outputs};
+use crate::serde::compress::Compression;
+use crate::serde::TileOrder;
+use crate::Error;
+
+pub(crate) struct Asset {
+    pub input: PathBuf,
+    pub palette: Option<PathBuf>,
+    pub bpp: Option<usize>,
+    pub conversion: Conversion,
+    pub outputs: Outputs,
+}
+
+impl Asset {
+    pub fn build(&self) -> Result<(), Error> {
+        let canvas = cli::load_input(&self.input, self.palette.as_deref(), self.bpp)?;
+        cli::write_outputs(&canvas, &self.outputs, &self.conversion)
+    }
+
+    /// Files that affect this asset's output.
+    pub fn sources(&self) -> Vec<PathBuf> {
+        [Some(&self.input), self.palette.as_ref()].into_iter().flatten().cloned().collect()
+    }
+}
+
+/// Every asset in a manifest, by name. Assets that don't parse keep their error, so one typo
+/// doesn't stop the rest from building.
+pub(crate) struct Manifest {
+    pub assets: Vec<(String, Result<Asset, Error>)>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest, Error> {
+        let text = fs::read_to_string(path)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        Manifest::parse(&text, base)
+    }
+
+    pub fn parse(text: &str, base: &Path) -> Result<Manifest, Error> {
+        let table: Table = text.parse().map_err(|e| Error::InvalidManifest(format!("{e}")))?;
+        let defaults = match table.get("defaults") {
+            Some(Value::Table(defaults)) => defaults.clone(),
+            Some(_) => return Err(Error::InvalidManifest("`defaults` should be a table".to_owned())),
+            None => Table::new(),
+        };
+        let assets = match table.get("asset") {
+            Some(Value::Array(assets)) => assets,
+            _ => return Err(Error::InvalidManifest("no [[asset]] entries".to_owned())),
+        };
+
+        let assets = assets.iter().enumerate().map(|(i, asset)| {
+            let name = asset.get("input")
+                .and_then(Value::as_str)
+                .map(str::to_owned)
+                .unwrap_or_else(|| format!("asset #{}", i + 1));
+            let asset = match asset {
+                Value::Table(asset) => parse_asset(asset, &defaults, base),
+                _ => Err(Error::InvalidManifest("asset should be a table".to_owned())),
+            };
+            (name, asset)
+        }).collect();
+        Ok(Manifest { assets })
+    }
+}
+
+fn parse_asset(asset: &Table, defaults: &Table, base: &Path) -> Result<Asset, Error> {
+    let get = |key: &str| asset.get(key).or_else(|| defaults.get(key));
+    let string = |key: &str| -> Result<Option<&str>, Error> {
+        match get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.as_str())),
+            Some(_) => Err(Error::InvalidManifest(format!("`{key}` should be a string"))),
+        }
+    };
+    let path = |key: &str| -> Result<Option<PathBuf>, Error> {
+        Ok(string(key)?.map(|p| base.join(p)))
+    };
+    // outputs can be a path, or false to skip them
+    let output = |key: &str, default: PathBuf| -> Result<Option<PathBuf>, Error> {
+        match get(key) {
+            None => Ok(Some(default)),
+            Some(Value::Boolean(false)) => Ok(None),
+            Some(Value::String(p)) => Ok(Some(base.join(p))),
+            Some(_) => Err(Error::InvalidManifest(format!("`{key}` should be a path or false"))),
+        }
+    };
+
+    let input = path("input")?.ok_or_else(|| Error::InvalidManifest("missing `input`".to_owned()))?;
+    let bpp = match get("bpp") {
+        None => None,
+        Some(Value::Integer(bpp)) => Some(cli::parse_bpp(&bpp.to_string())?),
+        Some(_) => return Err(Error::InvalidManifest("`bpp` should be a number".to_owned())),
+    };
+    let palette_slot = match get("palette_slot") {
+        None => 0,
+        Some(Value::Integer(slot)) => cli::parse_palette_slot(&slot.to_string())?,
+        Some(_) => return Err(Error::InvalidManifest("`palette_slot` should be a number".to_owned())),
+    };
+    let order = match string("order")? {
+        Some(order) => cli::parse_order(order)?,
+        None => TileOrder::Linear,
+    };
+    let format = match string("format")? {
+        Some(format) => OutputFormat::parse(format)?,
+        None => OutputFormat::Bin,
+    };
+    let compression = match string("compress")? {
+        Some(compression) => cli::parse_compression(compression)?,
+        None => Compression::None,
+    };
+
+    let prefix = match (path("output")?, path("out_dir")?) {
+        (Some(prefix), _) => prefix,
+        (None, Some(dir)) => dir.join(input.file_stem().unwrap_or_default()),
+        (None, None) => input.clone(),
+    };
+    let defaults = Outputs::from_prefix(&prefix, format);
+    let outputs = Outputs {
+        tiles: output("tiles", defaults.tiles)?
+            .ok_or_else(|| Error::InvalidManifest("`tiles` can't be skipped".to_owned()))?,
+        palette: output("cgram", defaults.palette.unwrap_or_default())?,
+        tilemap: output("tilemap", defaults.tilemap.unwrap_or_default())?,
+    };
+
+    Ok(Asset {
+        input,
+        palette: path("palette")?,
+        bpp,
+        conversion: Conversion { format, order, palette_slot, compression },
+        outputs,
+    })
+}
+
+/// Builds every asset in the manifest, reporting each failure and carrying on with the rest.
+pub fn build(path: &Path) -> Result<(), Error> {
+    let manifest = Manifest::load(path)?;
+    let failed = build_all(&manifest);
+    if failed > 0 {
+        return Err(Error::AssetsFailed(failed, manifest.assets.len()));
+    }
+    Ok(())
+}
+
+/// Returns the number of assets that failed.
+pub(crate) fn build_all(manifest: &Manifest) -> usize {
+    let mut failed = 0;
+    for (name, asset) in &manifest.assets {
+        let result = asset.as_ref()
+            .map_err(|e| e.to_string())
+            .and_then(|asset| asset.build().map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => println!("built {name}"),
+            Err(e) => {
+                eprintln!("error: {name}: {e}");
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_and_overrides() {
+        let manifest = Manifest::parse(r#"
+            [defaults]
+            bpp = 4
+            out_dir = "build"
+
+            [[asset]]
+            input = "hero.png"
+            order = "sprite16"
+            palette_slot = 2
+            tilemap = false
+
+            [[asset]]
+            input = "font.png"
+            bpp = 2
+            tiles = "font.chr"
+
+            [[asset]]
+            input = "broken.png"
+            bpp = 5
+        "#, Path::new("gfx")).unwrap();
+
+        let hero = manifest.assets[0].1.as_ref().unwrap();
+        assert_eq!(hero.bpp, Some(4));
+        assert_eq!(hero.conversion.order, TileOrder::Sprite16);
+        assert_eq!(hero.outputs.tiles, Path::new("gfx/build/hero.bin"));
+        assert_eq!(hero.outputs.tilemap, None);
+
+        let font = manifest.assets[1].1.as_ref().unwrap();
+        assert_eq!(font.bpp, Some(2));
+        assert_eq!(font.outputs.tiles, Path::new("gfx/font.chr"));
+        assert_eq!(font.outputs.palette.as_deref(), Some(Path::new("gfx/build/font.pal")));
+
+        assert_eq!(manifest.assets[2].0, "broken.png");
+        assert!(manifest.assets[2].1.is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Watch mode: converts once, then keeps converting whenever an input file changes, so the next
+//! ROM build always picks up the latest art.
+//!
+//! Files are polled for their modification time instead of using OS notifications. It's cheap
+//! at these file counts and also works on network shares and in containers.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use crate::cli::manifest::{self, Manifest};
+use crate::cli::{convert, Options};
+use crate::Error;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Editors often save in several steps (truncate, write, rename), so give them a moment to
+/// finish before reading the file.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// A set of files and their last seen modification times.
+struct Stamps {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Stamps {
+    fn new(files: Vec<PathBuf>) -> Stamps {
+        let files = files.into_iter().map(|f| {
+            let stamp = modified(&f);
+            (f, stamp)
+        }).collect();
+        Stamps { files }
+    }
+
+    /// Updates the stamps, returning whether any of them changed.
+    fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (file, stamp) in &mut self.files {
+            let new = modified(file);
+            if new != *stamp {
+                *stamp = new;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+pub fn watch_convert(options: Options) -> Result<(), Error> {
+    let sources = [Some(options.input.clone()), options.palette.clone()].into_iter().flatten().collect();
+    let mut stamps = Stamps::new(sources);
+    let name = options.input.display().to_string();
+
+    report(&name, convert(&options));
+    println!("watching {name}...");
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        if stamps.changed() {
+            thread::sleep(SETTLE_TIME);
+            stamps.changed();
+            report(&name, convert(&options));
+        }
+    }
+}
+
+pub fn watch_manifest(path: &Path) -> Result<(), Error> {
+    let mut manifest_stamp = Stamps::new(vec![path.to_owned()]);
+    let (mut manifest, mut stamps) = load(path)?;
+    manifest::build_all(&manifest);
+    println!("watching {} assets from {}...", manifest.assets.len(), path.display());
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        if manifest_stamp.changed() {
+            thread::sleep(SETTLE_TIME);
+            manifest_stamp.changed();
+            // keep watching the old asset list if the new manifest doesn't parse
+            match load(path) {
+                Ok(loaded) => {
+                    (manifest, stamps) = loaded;
+                    println!("reloaded {}", path.display());
+                    manifest::build_all(&manifest);
+                }
+                Err(e) => eprintln!("error: {}: {e}", path.display()),
+            }
+            continue;
+        }
+
+        let changed: Vec<usize> = stamps.iter_mut()
+            .enumerate()
+            .filter_map(|(i, stamps)| stamps.changed().then_some(i))
+            .collect();
+        if changed.is_empty() {
+            continue;
+        }
+        thread::sleep(SETTLE_TIME);
+        for i in changed {
+            stamps[i].changed();
+            let (name, asset) = &manifest.assets[i];
+            if let Ok(asset) = asset {
+                report(name, asset.build());
+            }
+        }
+    }
+}
+
+/// Loads a manifest along with stamps for each asset's source files.
+fn load(path: &Path) -> Result<(Manifest, Vec<Stamps>), Error> {
+    let manifest = Manifest::load(path)?;
+    let stamps = manifest.assets.iter()
+        .map(|(_, asset)| Stamps::new(asset.as_ref().map(|a| a.sources()).unwrap_or_default()))
+        .collect();
+    Ok((manifest, stamps))
+}
+
+fn report(name: &str, result: Result<(), Error>) {
+    match result {
+        Ok(()) => println!("built {name}"),
+        Err(e) => eprintln!("error: {name}: {e}"),
+    }
+}
@@ -0,0 +1,343 @@
+//! The `:` command line.
+//!
+//! ```text
+//! :w [path]                  save the project, to where it was last saved/opened if no path
+//! :e path                    open a project or PNG
+//! :resize W H               resize the canvas (also `:resize WxH`, or `:size`)
+//! :set bpp=N                 switch the palette to 1, 2, 3, 4 or 8 bpp (also `:bpp N`)
+//! :color INDEX HEX           set a palette color from a BGR555 value, like `:color 1 7fff`
+//! :export WHAT path          write tiles, palette, tilemap, asm or source
+//! ```
+//!
+//! Tab completes command names, `:set` options, export targets and paths. Up/down walk through history.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ExportKind {
+    Tiles,
+    Palette,
+    Tilemap,
+    Asm,
+    Source,
+}
+
+impl ExportKind {
+    pub const ALL: [ExportKind; 5] = [
+        ExportKind::Tiles,
+        ExportKind::Palette,
+        ExportKind::Tilemap,
+        ExportKind::Asm,
+        ExportKind::Source,
+    ];
+
+    pub fn parse(name: &str) -> Option<ExportKind> {
+        ExportKind::ALL.into_iter().find(|kind| kind.to_string() == name)
+    }
+}
+
+impl std::fmt::Display for ExportKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            ExportKind::Tiles => "tiles",
+            ExportKind::Palette => "palette",
+            ExportKind::Tilemap => "tilemap",
+            ExportKind::Asm => "asm",
+            ExportKind::Source => "source",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Command {
+    Write(Option<PathBuf>),
+    Edit(PathBuf),
+    Size(usize, usize),
+    Bpp(usize),
+    /// Palette index and BGR555 value.
+    Color(usize, u16),
+    Export(ExportKind, PathBuf),
+}
+
+/// Command names, in the order tab completion offers them.
+const NAMES: [&str; 6] = ["w", "e", "resize", "set", "color", "export"];
+
+/// What `:set` can change.
+const OPTIONS: [&str; 1] = ["bpp"];
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, Error> {
+        let line = line.trim().trim_start_matches(':');
+        let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        let path = || -> Result<PathBuf, Error> {
+            if rest.is_empty() {
+                Err(invalid(format!(":{name} needs a path")))
+            } else {
+                Ok(PathBuf::from(rest))
+            }
+        };
+
+        match name {
+            "w" | "write" => Ok(Command::Write((!rest.is_empty()).then(|| PathBuf::from(rest)))),
+            "e" | "edit" => Ok(Command::Edit(path()?)),
+            "resize" | "size" => {
+                let (w, h) = match args[..] {
+                    [size] => size.split_once('x').ok_or_else(|| invalid(format!("bad size {size:?}")))?,
+                    [w, h] => (w, h),
+                    _ => return Err(invalid(format!("usage: :{name} W H"))),
+                };
+                match (w.parse(), h.parse()) {
+                    (Ok(w), Ok(h)) => Ok(Command::Size(w, h)),
+                    _ => Err(invalid(format!("bad size {w}x{h}"))),
+                }
+            }
+            "set" => match args[..] {
+                [option] => match option.split_once('=') {
+                    Some(("bpp", bpp)) => parse_bpp(bpp),
+                    Some((key, _)) => Err(invalid(format!("unknown option {key:?}, try {}", OPTIONS.join(", ")))),
+                    None => Err(invalid("usage: :set OPTION=VALUE".to_owned())),
+                },
+                _ => Err(invalid("usage: :set OPTION=VALUE".to_owned())),
+            },
+            "bpp" => match args[..] {
+                [bpp] => parse_bpp(bpp),
+                _ => Err(invalid("usage: :bpp N".to_owned())),
+            },
+            "color" => match args[..] {
+                [idx, hex] => {
+                    let idx = idx.parse().map_err(|_| invalid(format!("bad color index {idx}")))?;
+                    let hex = hex.trim_start_matches("0x").trim_start_matches('$');
+                    let value = u16::from_str_radix(hex, 16)
+                        .ok()
+                        .filter(|v| *v <= 0x7fff)
+                        .ok_or_else(|| invalid(format!("bad BGR555 value {hex}, should be 0-7fff")))?;
+                    Ok(Command::Color(idx, value))
+                }
+                _ => Err(invalid("usage: :color INDEX HEX".to_owned())),
+            },
+            "export" => {
+                let (what, file) = rest.split_once(' ').unwrap_or((rest, ""));
+                let kind = ExportKind::parse(what)
+                    .ok_or_else(|| invalid(format!("can't export {what:?}, try tiles, palette, tilemap, asm or source")))?;
+                if file.trim().is_empty() {
+                    return Err(invalid(format!(":export {what} needs a path")));
+                }
+                Ok(Command::Export(kind, PathBuf::from(file.trim())))
+            }
+            "" => Err(invalid("no command".to_owned())),
+            _ => Err(invalid(format!("unknown command :{name}"))),
+        }
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::InvalidCommand(msg)
+}
+
+fn parse_bpp(bpp: &str) -> Result<Command, Error> {
+    match bpp.parse() {
+        Ok(bpp @ (1..=4 | 8)) => Ok(Command::Bpp(bpp)),
+        Ok(bpp) => Err(Error::UnsupportedBpp(bpp)),
+        Err(_) => Err(Error::Parse("bpp", bpp.to_owned())),
+    }
+}
+
+/// Returns: every way of finishing the word being typed, as whole lines.
+pub fn complete(line: &str) -> Vec<String> {
+    let Some((name, rest)) = line.split_once(' ') else {
+        return NAMES.iter().filter(|n| n.starts_with(line)).map(|n| n.to_string()).collect();
+    };
+    match name {
+        "export" => match rest.split_once(' ') {
+            None => ExportKind::ALL.iter()
+                .map(|kind| kind.to_string())
+                .filter(|kind| kind.starts_with(rest))
+                .map(|kind| format!("{name} {kind} "))
+                .collect(),
+            Some((kind, path)) => complete_path(path).into_iter().map(|p| format!("{name} {kind} {p}")).collect(),
+        },
+        "set" => OPTIONS.iter()
+            .filter(|option| option.starts_with(rest))
+            .map(|option| format!("{name} {option}="))
+            .collect(),
+        "w" | "write" | "e" | "edit" => complete_path(rest).into_iter().map(|p| format!("{name} {p}")).collect(),
+        _ => vec![],
+    }
+}
+
+/// Files and directories starting with `partial`, with directories ending in a slash.
+fn complete_path(partial: &str) -> Vec<String> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(i) => (&partial[..=i], &partial[i + 1..]),
+        None => ("", partial),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { Path::new(".") } else { Path::new(dir) }) else {
+        return vec![];
+    };
+    let mut found: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            let slash = if e.path().is_dir() { "/" } else { "" };
+            (name.starts_with(prefix) && !name.starts_with('.')).then(|| format!("{dir}{name}{slash}"))
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+/// The longest start shared by every candidate.
+pub fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else { return String::new() };
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first.bytes().zip(candidate.bytes()).take(len).take_while(|(a, b)| a == b).count();
+    }
+    // don't cut a multi-byte character in half
+    while !first.is_char_boundary(len) {
+        len -= 1;
+    }
+    first[..len].to_owned()
+}
+
+/// The text being typed, plus what was typed before.
+#[derive(Default)]
+pub(crate) struct CommandLine {
+    pub open: bool,
+    pub text: String,
+    history: Vec<String>,
+    /// Which history entry is showing, counting back from the newest.
+    history_pos: Option<usize>,
+    /// Result of the last command, or the completion candidates.
+    pub message: Option<String>,
+}
+
+impl CommandLine {
+    pub fn open(&mut self) {
+        self.open = true;
+        self.text.clear();
+        self.history_pos = None;
+        self.message = None;
+    }
+
+    pub fn close(&mut self) {
+        self.open = false;
+        self.text.clear();
+        self.history_pos = None;
+    }
+
+    /// Takes the typed line, remembering it, and closes the command line.
+    pub fn submit(&mut self) -> String {
+        let line = self.text.trim().to_owned();
+        if !line.is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        self.close();
+        line
+    }
+
+    pub fn history_back(&mut self) {
+        if self.history.is_empty() {
+            return;
+        }
+        let pos = self.history_pos.map_or(0, |p| Ord::min(p + 1, self.history.len() - 1));
+        self.history_pos = Some(pos);
+        self.text = self.history[self.history.len() - 1 - pos].clone();
+    }
+
+    pub fn history_forward(&mut self) {
+        match self.history_pos {
+            Some(0) | None => {
+                self.history_pos = None;
+                self.text.clear();
+            }
+            Some(pos) => {
+                self.history_pos = Some(pos - 1);
+                self.text = self.history[self.history.len() - pos].clone();
+            }
+        }
+    }
+
+    /// Fills in as much as all the completions agree on, listing them if that's nothing new.
+    pub fn complete(&mut self) {
+        let candidates = complete(&self.text);
+        let prefix = common_prefix(&candidates);
+        if prefix.len() > self.text.len() {
+            self.text = prefix;
+            self.message = None;
+        } else if candidates.len() > 1 {
+            self.message = Some(candidates.join("  "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse(":resize 32 32").unwrap(), Command::Size(32, 32));
+        assert_eq!(Command::parse("resize 32x16").unwrap(), Command::Size(32, 16));
+        assert_eq!(Command::parse("size 8 24").unwrap(), Command::Size(8, 24));
+        assert!(Command::parse("resize 32").is_err());
+        assert_eq!(Command::parse(":set bpp=4").unwrap(), Command::Bpp(4));
+        assert!(Command::parse("set bpp=5").is_err());
+        assert!(Command::parse("set bpp 4").is_err());
+        assert!(Command::parse("set zoom=2").is_err());
+        assert_eq!(Command::parse("bpp 4").unwrap(), Command::Bpp(4));
+        assert!(Command::parse("bpp 5").is_err());
+        assert_eq!(Command::parse("color 3 0x7fff").unwrap(), Command::Color(3, 0x7fff));
+        assert!(Command::parse("color 3 8000").is_err());
+        assert_eq!(Command::parse("w").unwrap(), Command::Write(None));
+        assert_eq!(Command::parse("e my art.snesp").unwrap(), Command::Edit(PathBuf::from("my art.snesp")));
+        assert_eq!(
+            Command::parse("export palette out/pal.bin").unwrap(),
+            Command::Export(ExportKind::Palette, PathBuf::from("out/pal.bin")),
+        );
+        assert!(Command::parse("export sound x").is_err());
+        assert!(Command::parse("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_completion_and_history() {
+        assert_eq!(complete("ex"), ["export"]);
+        assert_eq!(complete("re"), ["resize"]);
+        assert_eq!(complete("set b"), ["set bpp="]);
+        assert_eq!(complete("export t"), ["export tiles ", "export tilemap "]);
+        assert_eq!(common_prefix(&complete("export t")), "export tile");
+
+        let dir = std::env::temp_dir().join(format!("snes-paint-complete-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("art")).unwrap();
+        fs::write(dir.join("art.snesp"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        let dir_name = dir.display().to_string();
+        assert_eq!(complete(&format!("e {dir_name}/a")), [format!("e {dir_name}/art.snesp"), format!("e {dir_name}/art/")]);
+        assert_eq!(complete(&format!("w {dir_name}/art.")), [format!("w {dir_name}/art.snesp")]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut line = CommandLine::default();
+        for command in ["set bpp=2", "resize 8 8", "resize 8 8"] {
+            line.open();
+            line.text = command.to_owned();
+            line.submit();
+        }
+        line.open();
+        line.history_back();
+        assert_eq!(line.text, "resize 8 8");
+        line.history_back();
+        assert_eq!(line.text, "set bpp=2");
+        line.history_back();
+        assert_eq!(line.text, "set bpp=2");
+        line.history_forward();
+        assert_eq!(line.text, "resize 8 8");
+        line.history_forward();
+        assert_eq!(line.text, "");
+    }
+}
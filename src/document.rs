@@ -0,0 +1,161 @@
+//! Open documents, one per tab: a canvas, where it's saved, and its own undo history.
+//!
+//! Undo works on whole snapshots of the canvas's frames and palette. The app calls
+//! [`History::record`] once nothing is held down, so a stroke, a fill or a paste is one step no
+//! matter how many pixels it touched.
+
+use std::path::{Path, PathBuf};
+use crate::paint::{Canvas, Snapshot};
+use crate::rom::Region;
+
+/// Undo steps kept per document.
+pub const MAX_UNDO: usize = 100;
+
+pub(crate) struct History {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// The canvas as of the last record, undo or redo.
+    current: Snapshot,
+    /// The canvas as of the last save or open. None if what's on disk isn't known to match any
+    /// of it, like after restoring an autosave.
+    saved: Option<Snapshot>,
+}
+
+impl History {
+    pub fn new(canvas: &Canvas) -> History {
+        History {
+            undo: vec![],
+            redo: vec![],
+            current: canvas.snapshot(),
+            saved: Some(canvas.snapshot()),
+        }
+    }
+
+    /// Makes an undo step if the canvas changed since the last one.
+    pub fn record(&mut self, canvas: &Canvas) {
+        if self.current.matches(canvas) {
+            return;
+        }
+        self.undo.push(std::mem::replace(&mut self.current, canvas.snapshot()));
+        if self.undo.len() > MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    pub fn undo(&mut self, canvas: &mut Canvas) {
+        // anything not recorded yet is lost otherwise
+        self.record(canvas);
+        if let Some(previous) = self.undo.pop() {
+            self.redo.push(std::mem::replace(&mut self.current, previous.clone()));
+            canvas.restore(previous);
+        }
+    }
+
+    pub fn redo(&mut self, canvas: &mut Canvas) {
+        self.record(canvas);
+        if let Some(next) = self.redo.pop() {
+            self.undo.push(std::mem::replace(&mut self.current, next.clone()));
+            canvas.restore(next);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn mark_saved(&mut self, canvas: &Canvas) {
+        self.record(canvas);
+        self.saved = Some(self.current.clone());
+    }
+
+    /// Whether the canvas, as of the last record, differs from what was last saved.
+    pub fn is_dirty(&self) -> bool {
+        self.saved.as_ref() != Some(&self.current)
+    }
+}
+
+pub(crate) struct Document {
+    pub canvas: Canvas,
+    /// Where it was opened from or last saved to.
+    pub path: Option<PathBuf>,
+    pub history: History,
+    /// Where in the ROM the canvas was loaded from, if it was.
+    pub rom_region: Option<Region>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Document::new(Canvas::new(), None)
+    }
+}
+
+impl Document {
+    pub fn new(canvas: Canvas, path: Option<PathBuf>) -> Document {
+        let history = History::new(&canvas);
+        Document { canvas, path, history, rom_region: None }
+    }
+
+    /// A document brought back from an autosave, so unsaved until it's saved again.
+    pub fn recovered(canvas: Canvas, path: Option<PathBuf>) -> Document {
+        let mut doc = Document::new(canvas, path);
+        doc.history.saved = None;
+        doc
+    }
+
+    /// What the tab says.
+    pub fn name(&self) -> String {
+        self.path.as_deref()
+            .and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "untitled".to_owned())
+    }
+
+    /// Untitled and untouched, so opening a file can just replace it.
+    pub fn is_blank(&self) -> bool {
+        self.path.is_none() && !self.history.is_dirty() && !self.history.can_undo()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_redo_and_dirty() {
+        let mut doc = Document::default();
+        assert!(doc.is_blank());
+        doc.canvas.color_idx = 1;
+        doc.canvas.paint(0, 0);
+        doc.canvas.paint(1, 0);
+        doc.history.record(&doc.canvas);
+        assert!(doc.history.is_dirty());
+        doc.canvas.paint(2, 0);
+
+        // the unrecorded pixel goes first, then the whole first step
+        doc.history.undo(&mut doc.canvas);
+        assert_eq!(doc.canvas.grid().get(2, 0), 0);
+        assert_eq!(doc.canvas.grid().get(1, 0), 1);
+        doc.history.undo(&mut doc.canvas);
+        assert_eq!(doc.canvas.grid().get(0, 0), 0);
+        assert!(!doc.history.is_dirty());
+        assert!(!doc.history.can_undo());
+
+        doc.history.redo(&mut doc.canvas);
+        assert_eq!(doc.canvas.grid().get(1, 0), 1);
+        doc.history.mark_saved(&doc.canvas);
+        assert!(!doc.history.is_dirty());
+
+        // a new edit drops what could have been redone
+        doc.canvas.paint(5, 5);
+        doc.history.record(&doc.canvas);
+        assert!(!doc.history.can_redo());
+        assert!(doc.history.is_dirty());
+
+        assert!(Document::recovered(Canvas::new(), None).history.is_dirty());
+    }
+}
@@ -0,0 +1,359 @@
+//! Keybindings, read from `keymap.toml` in the user's config dir (`~/.config/snes-paint` on
+//! Linux). Anything not in the file keeps its default.
+//!
+//! ```toml
+//! palette_forward = "shift+j"
+//! capture_brush = "ctrl+b"
+//! yank = "y"
+//! ```
+//!
+//! Modifiers are `ctrl` (cmd on mac), `alt` and `shift`; keys are egui key names, so letters,
+//! digits, `escape`, `tab`, `up` and so on.
+
+use std::fs;
+use std::path::PathBuf;
+use eframe::egui::{Key, KeyboardShortcut, Modifiers};
+use toml::{Table, Value};
+use crate::settings;
+use crate::Error;
+
+pub const FILE_NAME: &str = "keymap.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Action {
+    PaletteForward,
+    PaletteBackward,
+    SidebarFile,
+    SidebarCanvas,
+    SidebarRom,
+    SidebarVram,
+    SidebarSprite,
+    SidebarKeys,
+    OnionSkin,
+    CaptureBrush,
+    CursorLeft,
+    CursorRight,
+    CursorUp,
+    CursorDown,
+    CursorPaint,
+    CursorFill,
+    NextTile,
+    PreviousTile,
+    /// Pressed twice, like vim's `gg`.
+    GotoFirstRow,
+    GotoLastRow,
+    NormalMode,
+    VisualMode,
+    InsertMode,
+    Yank,
+    Delete,
+    Paste,
+    Undo,
+    Redo,
+    CopySelection,
+    PasteSelection,
+    NewDocument,
+    CloseDocument,
+    NextDocument,
+    PreviousDocument,
+}
+
+impl Action {
+    pub const ALL: [Action; 34] = [
+        Action::PaletteForward,
+        Action::PaletteBackward,
+        Action::SidebarFile,
+        Action::SidebarCanvas,
+        Action::SidebarRom,
+        Action::SidebarVram,
+        Action::SidebarSprite,
+        Action::SidebarKeys,
+        Action::OnionSkin,
+        Action::CaptureBrush,
+        Action::CursorLeft,
+        Action::CursorRight,
+        Action::CursorUp,
+        Action::CursorDown,
+        Action::CursorPaint,
+        Action::CursorFill,
+        Action::NextTile,
+        Action::PreviousTile,
+        Action::GotoFirstRow,
+        Action::GotoLastRow,
+        Action::NormalMode,
+        Action::VisualMode,
+        Action::InsertMode,
+        Action::Yank,
+        Action::Delete,
+        Action::Paste,
+        Action::Undo,
+        Action::Redo,
+        Action::CopySelection,
+        Action::PasteSelection,
+        Action::NewDocument,
+        Action::CloseDocument,
+        Action::NextDocument,
+        Action::PreviousDocument,
+    ];
+
+    /// The name used in the config file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::PaletteForward => "palette_forward",
+            Action::PaletteBackward => "palette_backward",
+            Action::SidebarFile => "sidebar_file",
+            Action::SidebarCanvas => "sidebar_canvas",
+            Action::SidebarRom => "sidebar_rom",
+            Action::SidebarVram => "sidebar_vram",
+            Action::SidebarSprite => "sidebar_sprite",
+            Action::SidebarKeys => "sidebar_keys",
+            Action::OnionSkin => "onion_skin",
+            Action::CaptureBrush => "capture_brush",
+            Action::CursorLeft => "cursor_left",
+            Action::CursorRight => "cursor_right",
+            Action::CursorUp => "cursor_up",
+            Action::CursorDown => "cursor_down",
+            Action::CursorPaint => "cursor_paint",
+            Action::CursorFill => "cursor_fill",
+            Action::NextTile => "next_tile",
+            Action::PreviousTile => "previous_tile",
+            Action::GotoFirstRow => "goto_first_row",
+            Action::GotoLastRow => "goto_last_row",
+            Action::NormalMode => "normal_mode",
+            Action::VisualMode => "visual_mode",
+            Action::InsertMode => "insert_mode",
+            Action::Yank => "yank",
+            Action::Delete => "delete",
+            Action::Paste => "paste",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::CopySelection => "copy_selection",
+            Action::PasteSelection => "paste_selection",
+            Action::NewDocument => "new_document",
+            Action::CloseDocument => "close_document",
+            Action::NextDocument => "next_document",
+            Action::PreviousDocument => "previous_document",
+        }
+    }
+
+    pub fn default_shortcut(&self) -> KeyboardShortcut {
+        let (modifiers, key) = match self {
+            Action::PaletteForward => (Modifiers::SHIFT, Key::J),
+            Action::PaletteBackward => (Modifiers::SHIFT, Key::K),
+            Action::SidebarFile => (Modifiers::ALT, Key::F),
+            Action::SidebarCanvas => (Modifiers::ALT, Key::C),
+            Action::SidebarRom => (Modifiers::ALT, Key::R),
+            Action::SidebarVram => (Modifiers::ALT, Key::V),
+            Action::SidebarSprite => (Modifiers::ALT, Key::S),
+            Action::SidebarKeys => (Modifiers::ALT, Key::K),
+            Action::OnionSkin => (Modifiers::NONE, Key::O),
+            Action::CaptureBrush => (Modifiers::COMMAND, Key::B),
+            Action::CursorLeft => (Modifiers::NONE, Key::H),
+            Action::CursorRight => (Modifiers::NONE, Key::L),
+            Action::CursorUp => (Modifiers::NONE, Key::K),
+            Action::CursorDown => (Modifiers::NONE, Key::J),
+            Action::CursorPaint => (Modifiers::NONE, Key::F),
+            Action::CursorFill => (Modifiers::SHIFT, Key::F),
+            Action::NextTile => (Modifiers::NONE, Key::W),
+            Action::PreviousTile => (Modifiers::NONE, Key::B),
+            Action::GotoFirstRow => (Modifiers::NONE, Key::G),
+            Action::GotoLastRow => (Modifiers::SHIFT, Key::G),
+            Action::NormalMode => (Modifiers::NONE, Key::Escape),
+            Action::VisualMode => (Modifiers::NONE, Key::V),
+            Action::InsertMode => (Modifiers::NONE, Key::I),
+            Action::Yank => (Modifiers::NONE, Key::Y),
+            Action::Delete => (Modifiers::NONE, Key::D),
+            Action::Paste => (Modifiers::NONE, Key::P),
+            Action::Undo => (Modifiers::COMMAND, Key::Z),
+            Action::Redo => (Modifiers::COMMAND | Modifiers::SHIFT, Key::Z),
+            Action::CopySelection => (Modifiers::COMMAND, Key::C),
+            Action::PasteSelection => (Modifiers::COMMAND, Key::V),
+            Action::NewDocument => (Modifiers::COMMAND, Key::N),
+            Action::CloseDocument => (Modifiers::COMMAND, Key::W),
+            Action::NextDocument => (Modifiers::COMMAND, Key::PageDown),
+            Action::PreviousDocument => (Modifiers::COMMAND, Key::PageUp),
+        };
+        KeyboardShortcut::new(modifiers, key)
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Action::PaletteForward => "Next palette color",
+            Action::PaletteBackward => "Previous palette color",
+            Action::SidebarFile => "File sidebar",
+            Action::SidebarCanvas => "Canvas sidebar",
+            Action::SidebarRom => "ROM sidebar",
+            Action::SidebarVram => "VRAM sidebar",
+            Action::SidebarSprite => "Sprite sidebar",
+            Action::SidebarKeys => "Keys sidebar",
+            Action::OnionSkin => "Toggle onion skin",
+            Action::CaptureBrush => "Selection to brush",
+            Action::CursorLeft => "Cursor left",
+            Action::CursorRight => "Cursor right",
+            Action::CursorUp => "Cursor up",
+            Action::CursorDown => "Cursor down",
+            Action::CursorPaint => "Paint at cursor",
+            Action::CursorFill => "Fill at cursor",
+            Action::NextTile => "Next tile",
+            Action::PreviousTile => "Previous tile",
+            Action::GotoFirstRow => "First row (twice)",
+            Action::GotoLastRow => "Last row",
+            Action::NormalMode => "Normal mode",
+            Action::VisualMode => "Visual mode",
+            Action::InsertMode => "Insert mode",
+            Action::Yank => "Yank selection",
+            Action::Delete => "Delete selection",
+            Action::Paste => "Paste",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::CopySelection => "Copy selection",
+            Action::PasteSelection => "Paste copied pixels",
+            Action::NewDocument => "New tab",
+            Action::CloseDocument => "Close tab",
+            Action::NextDocument => "Next tab",
+            Action::PreviousDocument => "Previous tab",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+pub(crate) struct Keymap {
+    /// Indexed by `Action as usize`.
+    bindings: Vec<KeyboardShortcut>,
+    /// Entries in the config file that couldn't be used.
+    pub problems: Vec<String>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap {
+            bindings: Action::ALL.iter().map(Action::default_shortcut).collect(),
+            problems: vec![],
+        }
+    }
+}
+
+impl Keymap {
+    pub fn get(&self, action: Action) -> KeyboardShortcut {
+        self.bindings[action as usize]
+    }
+
+    /// Reads a keymap file over the defaults. Bad entries are skipped and noted in `problems`.
+    pub fn parse(text: &str) -> Result<Keymap, Error> {
+        let table: Table = text.parse().map_err(|e| Error::InvalidKeymap(format!("{e}")))?;
+        let mut keymap = Keymap::default();
+        for (name, value) in &table {
+            let Some(action) = Action::ALL.into_iter().find(|a| a.name() == name) else {
+                keymap.problems.push(format!("unknown action `{name}`"));
+                continue;
+            };
+            match value.as_str().and_then(parse_shortcut) {
+                Some(shortcut) => keymap.bindings[action as usize] = shortcut,
+                None => keymap.problems.push(format!("bad shortcut for `{name}`: {value}")),
+            }
+        }
+        Ok(keymap)
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        settings::config_dir().map(|dir| dir.join(FILE_NAME))
+    }
+
+    /// The user's keymap, or the defaults if there isn't one.
+    pub fn load() -> Result<Keymap, Error> {
+        match Keymap::path() {
+            Some(path) if path.exists() => Keymap::parse(&fs::read_to_string(path)?),
+            _ => Ok(Keymap::default()),
+        }
+    }
+
+    /// Writes every binding out, so there's a file to start editing from.
+    pub fn save(&self) -> Result<PathBuf, Error> {
+        let path = Keymap::path().ok_or_else(|| Error::InvalidKeymap("no config directory".to_owned()))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut table = Table::new();
+        for action in Action::ALL {
+            table.insert(action.name().to_owned(), Value::String(format_shortcut(&self.get(action))));
+        }
+        fs::write(&path, table.to_string())?;
+        Ok(path)
+    }
+
+    /// Pairs of actions bound to the same keys.
+    pub fn conflicts(&self) -> Vec<(Action, Action)> {
+        let mut conflicts = vec![];
+        for (i, a) in Action::ALL.iter().enumerate() {
+            for b in &Action::ALL[i + 1..] {
+                if self.get(*a) == self.get(*b) {
+                    conflicts.push((*a, *b));
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+/// Parses shortcuts like `ctrl+shift+b`.
+pub fn parse_shortcut(text: &str) -> Option<KeyboardShortcut> {
+    let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+    let name = parts.pop()?;
+    // from_name is picky about case for anything but letters
+    let key = Key::from_name(name).or_else(|| Key::ALL.iter().copied().find(|k| k.name().eq_ignore_ascii_case(name)))?;
+    let mut modifiers = Modifiers::NONE;
+    for part in parts {
+        match part.to_ascii_lowercase().as_str() {
+            "ctrl" | "cmd" | "command" => modifiers = modifiers | Modifiers::COMMAND,
+            "alt" => modifiers = modifiers | Modifiers::ALT,
+            "shift" => modifiers = modifiers | Modifiers::SHIFT,
+            _ => return None,
+        }
+    }
+    Some(KeyboardShortcut::new(modifiers, key))
+}
+
+/// Inverse of [`parse_shortcut`].
+pub fn format_shortcut(shortcut: &KeyboardShortcut) -> String {
+    let mut out = String::new();
+    if shortcut.modifiers.command {
+        out += "ctrl+";
+    }
+    if shortcut.modifiers.alt {
+        out += "alt+";
+    }
+    if shortcut.modifiers.shift {
+        out += "shift+";
+    }
+    out + &shortcut.logical_key.name().to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_round_trip_without_conflicts() {
+        let keymap = Keymap::default();
+        assert!(keymap.conflicts().is_empty());
+        for action in Action::ALL {
+            let shortcut = keymap.get(action);
+            assert_eq!(parse_shortcut(&format_shortcut(&shortcut)), Some(shortcut), "{}", action.name());
+        }
+    }
+
+    #[test]
+    fn test_parse_overrides_and_conflicts() {
+        let keymap = Keymap::parse("yank = \"ctrl+y\"\npaste = \"y\"\nfly = \"x\"\ndelete = \"hyper+d\"\n").unwrap();
+        assert_eq!(keymap.get(Action::Yank), KeyboardShortcut::new(Modifiers::COMMAND, Key::Y));
+        assert_eq!(keymap.get(Action::Delete), Action::Delete.default_shortcut());
+        assert_eq!(keymap.problems.len(), 2);
+        assert!(keymap.conflicts().is_empty());
+
+        let keymap = Keymap::parse("paste = \"escape\"").unwrap();
+        assert_eq!(keymap.conflicts(), [(Action::NormalMode, Action::Paste)]);
+        assert!(Keymap::parse("yank = ").is_err());
+    }
+}
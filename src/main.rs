@@ -2,24 +2,107 @@ use std::fmt::{Display, Formatter};
 use crate::app::SnesPaintApp;
 
 mod app;
+mod cli;
+mod command;
+mod document;
+mod keymap;
 mod paint;
+mod recovery;
+mod rom;
 mod serde;
+mod settings;
+mod sprite;
+mod toast;
+mod vim;
 
 #[derive(Debug)]
 pub enum Error {
+    /// Width and height that can't be a canvas.
     InvalidCanvasSize(usize, usize),
+    UnsupportedBpp(usize),
+    InvalidPaletteFile(String),
+    InvalidProjectFile(String),
+    InvalidImage(String),
+    InvalidCompressedData(String),
+    InvalidManifest(String),
+    InvalidRom(String),
+    InvalidPatch(String),
+    InvalidDump(String),
+    InvalidMetasprite(String),
+    InvalidCommand(String),
+    InvalidKeymap(String),
+    /// What was being read, and the text that wasn't a valid one.
+    Parse(&'static str, String),
+    /// Expected and actual length of data written back into a ROM.
+    RomSizeMismatch(usize, usize),
+    /// How many assets failed, out of how many.
+    AssetsFailed(usize, usize),
+    Usage(String),
+    Io(std::io::Error),
+    /// The window couldn't be opened or died.
+    Gui(eframe::Error),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<eframe::Error> for Error {
+    fn from(e: eframe::Error) -> Self {
+        Error::Gui(e)
+    }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Error received!")
+        match self {
+            Error::InvalidCanvasSize(w, h) => write!(f, "can't make a {w}x{h} canvas, both sides need to be multiples of 8 up to {}", paint::MAX_CANVAS_SIZE),
+            Error::UnsupportedBpp(bpp) => write!(f, "{bpp} bpp isn't supported, only 1, 2, 3, 4 and 8"),
+            Error::InvalidPaletteFile(msg) => write!(f, "bad palette file: {msg}"),
+            Error::InvalidProjectFile(msg) => write!(f, "bad project file: {msg}"),
+            Error::InvalidImage(msg) => write!(f, "bad image: {msg}"),
+            Error::InvalidCompressedData(msg) => write!(f, "bad compressed data: {msg}"),
+            Error::InvalidManifest(msg) => write!(f, "bad manifest: {msg}"),
+            Error::InvalidRom(msg) => write!(f, "bad ROM: {msg}"),
+            Error::InvalidPatch(msg) => write!(f, "can't make patch: {msg}"),
+            Error::InvalidDump(msg) => write!(f, "bad dump: {msg}"),
+            Error::InvalidMetasprite(msg) => write!(f, "bad metasprite: {msg}"),
+            Error::InvalidCommand(msg) => write!(f, "{msg}"),
+            Error::InvalidKeymap(msg) => write!(f, "bad keymap: {msg}"),
+            Error::Parse(what, text) => write!(f, "couldn't read {what} {text:?}"),
+            Error::RomSizeMismatch(expected, actual) => write!(f, "expected {expected} bytes of tiles, got {actual}"),
+            Error::AssetsFailed(failed, total) => write!(f, "{failed} of {total} assets failed"),
+            Error::Usage(msg) => write!(f, "{msg}"),
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Gui(e) => write!(f, "window error: {e}"),
+        }
     }
 }
 
-impl std::error::Error for Error { }
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Gui(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 fn main() -> Result<(), Error> {
+    // any arguments means we're running headless
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = cli::run(&args) {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let native_options = eframe::NativeOptions::default();
-    eframe::run_native("SNES Paint", native_options, Box::new(|cc| Ok(Box::new(SnesPaintApp::new(cc))))).unwrap();
+    eframe::run_native("SNES Paint", native_options, Box::new(|cc| Ok(Box::new(SnesPaintApp::new(cc)))))?;
     Ok(())
 }
\ No newline at end of file
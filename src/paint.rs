@@ -4,13 +4,16 @@
 
 use std::fmt::Display;
 use std::ops::Index;
-use eframe::egui::{Color32, PointerButton, Rect, Rgba, Rounding, Stroke, Ui, Vec2};
+use eframe::egui::{Color32, InputState, PointerButton, Rect, Rgba, Rounding, Sense, Stroke, Ui, Vec2};
 use eframe::emath::Pos2;
 use eframe::epaint::RectShape;
-use crate::app::action;
+use crate::keymap::{Action, Keymap};
+use crate::serde::TileOrder;
 use crate::{serde, Error};
 
 // TODO: One-Channel for the SNES is not allowed
+#[allow(clippy::enum_variant_names, clippy::large_enum_variant)]
+#[derive(Clone)]
 pub(crate) enum Palette {
     OneChannel([Color32;2]),
     TwoChannel([Color32;4]),
@@ -42,13 +45,7 @@ impl Index<usize> for Palette {
     type Output = Color32;
 
     fn index(&self, index: usize) -> &Self::Output {
-        match &self {
-            Palette::OneChannel(c) => &c[index],
-            Palette::TwoChannel(c) => &c[index],
-            Palette::ThreeChannel(c) => &c[index],
-            Palette::FourChannel(c) => &c[index],
-            Palette::EightChannel(c) => &c[index],
-        }
+        &self.colors()[index]
     }
 }
 
@@ -62,44 +59,61 @@ impl Palette {
         ])
     }
 
-    pub fn get_color(&self, idx: usize) -> Color32 {
-        match &self {
-            Palette::OneChannel(c) => c[idx],
-            Palette::TwoChannel(c) => c[idx],
-            Palette::ThreeChannel(c) => c[idx],
-            Palette::FourChannel(c) => c[idx],
-            _ => { unimplemented!() }
+    /// Builds a palette from a list of colors, picking the smallest bpp mode that fits them.
+    /// Anything past 256 colors is dropped, and unused slots are left black.
+    pub(crate) fn from_colors(colors: &[Color32]) -> Palette {
+        let mut palette = match colors.len() {
+            0..=4 => Palette::TwoChannel(Default::default()),
+            5..=8 => Palette::ThreeChannel(Default::default()),
+            9..=16 => Palette::FourChannel(Default::default()),
+            _ => Palette::EightChannel([Color32::BLACK; 256]),
+        };
+        palette.fit_colors(colors);
+        palette
+    }
+
+    /// Copies `colors` into the palette without changing its bpp mode, truncating extra colors
+    /// and padding missing ones with black.
+    pub(crate) fn fit_colors(&mut self, colors: &[Color32]) {
+        for i in 0..self.size() {
+            self.set_color(i, colors.get(i).copied().unwrap_or(Color32::BLACK));
         }
     }
 
-    pub fn get_color_mut(&mut self, idx: usize) -> &mut Color32 {
+    pub fn colors(&self) -> &[Color32] {
         match self {
-            &mut Palette::OneChannel(ref mut c) => &mut c[idx],
-            &mut Palette::TwoChannel(ref mut c) => &mut c[idx],
-            &mut Palette::ThreeChannel(ref mut c) => &mut c[idx],
-            &mut Palette::FourChannel(ref mut c) => &mut c[idx],
-            _ => { unimplemented!() }
+            Palette::OneChannel(c) => &c[..],
+            Palette::TwoChannel(c) => &c[..],
+            Palette::ThreeChannel(c) => &c[..],
+            Palette::FourChannel(c) => &c[..],
+            Palette::EightChannel(c) => &c[..],
         }
     }
 
-    pub(crate) fn set_color(&mut self, idx: usize, color: Color32) {
+    pub(crate) fn colors_mut(&mut self) -> &mut [Color32] {
         match self {
-            &mut Palette::OneChannel(ref mut c) => c[idx] = color,
-            &mut Palette::TwoChannel(ref mut c) => c[idx] = color,
-            &mut Palette::ThreeChannel(ref mut c) => c[idx] = color,
-            &mut Palette::FourChannel(ref mut c) => c[idx] = color,
-            _ => { unimplemented!() }
+            Palette::OneChannel(c) => &mut c[..],
+            Palette::TwoChannel(c) => &mut c[..],
+            Palette::ThreeChannel(c) => &mut c[..],
+            Palette::FourChannel(c) => &mut c[..],
+            Palette::EightChannel(c) => &mut c[..],
         }
     }
 
+    pub fn get_color(&self, idx: usize) -> Color32 {
+        self.colors()[idx]
+    }
+
+    pub fn get_color_mut(&mut self, idx: usize) -> &mut Color32 {
+        &mut self.colors_mut()[idx]
+    }
+
+    pub(crate) fn set_color(&mut self, idx: usize, color: Color32) {
+        *self.get_color_mut(idx) = color;
+    }
+
     pub fn size(&self) -> usize {
-        match &self {
-            Palette::OneChannel(_) => 2,
-            Palette::TwoChannel(_) => 4,
-            Palette::ThreeChannel(_) => 8,
-            Palette::FourChannel(_) => 16,
-            _ => { unimplemented!() }
-        }
+        self.colors().len()
     }
 
     pub fn bpp(&self) -> usize {
@@ -112,32 +126,26 @@ impl Palette {
         }
     }
 
-    pub(crate) fn set_bpp(&mut self, bpp: usize) {
+    pub(crate) fn set_bpp(&mut self, bpp: usize) -> Result<(), Error> {
         let num_copy = 1 << Ord::min(bpp, self.bpp());
-        let curr_colors = match &self {
-            Palette::OneChannel(c) => &c[..],
-            Palette::TwoChannel(c) => &c[..],
-            Palette::ThreeChannel(c) => &c[..],
-            Palette::FourChannel(c) => &c[..],
-            Palette::EightChannel(c) => &c[..],
-        };
+        let curr_colors = self.colors();
         let mut new_palette = match bpp {
             1 => Palette::OneChannel(Default::default()),
             2 => Palette::TwoChannel(Default::default()),
             3 => Palette::ThreeChannel(Default::default()),
             4 => Palette::FourChannel(Default::default()),
             8 => Palette::EightChannel([Color32::BLACK;256]),
-            _ => { panic!("Invalid bpp setting: {bpp}!!!"); }
+            _ => return Err(Error::UnsupportedBpp(bpp)),
         };
 
-        for c in curr_colors[0..num_copy].iter().enumerate() {
-            new_palette.set_color(c.0, *c.1);
-        }
+        new_palette.colors_mut()[0..num_copy].copy_from_slice(&curr_colors[0..num_copy]);
 
         *self = new_palette;
+        Ok(())
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct CanvasGrid<const W: usize, const H: usize> {
     grid: [[usize;W];H]
 }
@@ -146,19 +154,6 @@ impl<const W: usize, const H: usize> CanvasGrid<W, H> {
     pub fn new() -> Self {
         CanvasGrid { grid: [[0usize;W];H] }
     }
-    
-    pub fn set_pixel(&mut self, row: usize, col: usize, idx: usize) {
-        if row > W || col > H {
-            panic!("Pixel set out of bounds! ({row}, {col}) out of bounds for {W}x{H} grid.");
-        }
-
-        self.grid[row][col] = idx;
-    }
-
-    #[inline]
-    pub const fn width(&self) -> usize { W }
-    #[inline]
-    pub const fn height(&self) -> usize { H }
 }
 
 /// A trait for being a two-dimensional grid of elements.
@@ -167,9 +162,8 @@ pub(crate) trait Grid<T: Clone> {
     fn set(&mut self, row: usize, col: usize, v: T);
     fn width(&self) -> usize;
     fn height(&self) -> usize;
-    fn idx_linear(&self, idx: usize) -> T {
-        self.get(idx % self.width(), idx / self.width())
-    }
+    /// A copy of the grid, since `Clone` can't be used on trait objects.
+    fn boxed_clone(&self) -> Box<dyn Grid<T>>;
 }
 
 // pulled out to global fn because traits with generic fns can't be turned into objects
@@ -177,8 +171,8 @@ pub fn subgrid<const W: usize, const H: usize>(grid: &dyn Grid<usize>, row_range
     let mut ret = CanvasGrid::<W, H>::new();
 
     // enumerating lets us index separately into ret and grid, so we don't get immediate OOB bug
-    for (ret_j, j) in (row_range.0..row_range.1).into_iter().enumerate() {
-        for (ret_i, i) in (col_range.0..col_range.1).into_iter().enumerate() {
+    for (ret_j, j) in (row_range.0..row_range.1).enumerate() {
+        for (ret_i, i) in (col_range.0..col_range.1).enumerate() {
             ret.set(ret_i, ret_j, grid.get(i, j));
         }
     }
@@ -206,25 +200,51 @@ impl<const W: usize, const H: usize> Grid<usize> for CanvasGrid<W, H> {
     fn height(&self) -> usize {
         H
     }
+
+    fn boxed_clone(&self) -> Box<dyn Grid<usize>> {
+        Box::new(self.clone())
+    }
 }
 
-macro_rules! impl_grid_on_canvas {
-    ($w:expr, $h:expr) => {
-        impl Grid<usize> for CanvasGrid<$w, $h> {
-            fn get(&self, row: usize, col: usize) -> usize {
-                self.grid[col][row]
-            }
-            fn set(&mut self, row: usize, col: usize, v: usize) {
-                self.grid[col][row] = v;
-            }
-            fn width(&self) -> usize {
-                $w
-            }
-            fn height(&self) -> usize {
-                $h
-            }
-        }
-    };
+/// A grid whose size is only known at runtime, for images and tile data that don't come in one
+/// of the canvas's fixed sizes.
+#[derive(Clone)]
+pub(crate) struct VecGrid {
+    width: usize,
+    height: usize,
+    grid: Vec<usize>,
+}
+
+impl VecGrid {
+    pub fn new(width: usize, height: usize) -> Self {
+        VecGrid { width, height, grid: vec![0; width * height] }
+    }
+}
+
+impl Grid<usize> for VecGrid {
+    #[inline]
+    fn get(&self, row: usize, col: usize) -> usize {
+        self.grid[col * self.width + row]
+    }
+
+    #[inline]
+    fn set(&mut self, row: usize, col: usize, v: usize) {
+        self.grid[col * self.width + row] = v;
+    }
+
+    #[inline]
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Grid<usize>> {
+        Box::new(self.clone())
+    }
 }
 
 impl<const W: usize, const H: usize> Index<usize> for CanvasGrid<W, H> {
@@ -243,71 +263,670 @@ impl<const W: usize, const H: usize> Default for CanvasGrid<W, H> {
     }
 }
 
+/// One animation frame.
+pub(crate) struct Frame {
+    pub(crate) grid: Box<dyn Grid<usize>>,
+    /// How long the frame shows for, in SNES frames (1/60 s).
+    pub(crate) duration: u16,
+}
+
+impl Frame {
+    pub(crate) fn new(grid: Box<dyn Grid<usize>>) -> Frame {
+        Frame { grid, duration: DEFAULT_FRAME_DURATION }
+    }
+}
+
+impl Clone for Frame {
+    fn clone(&self) -> Self {
+        Frame { grid: self.grid.boxed_clone(), duration: self.duration }
+    }
+}
+
+impl PartialEq for Frame {
+    fn eq(&self, other: &Frame) -> bool {
+        let (a, b) = (self.grid.as_ref(), other.grid.as_ref());
+        self.duration == other.duration
+            && a.width() == b.width()
+            && a.height() == b.height()
+            && (0..a.height()).all(|y| (0..a.width()).all(|x| a.get(x, y) == b.get(x, y)))
+    }
+}
+
+/// Everything undo puts back: the pixels of every frame and the palette.
+#[derive(Clone)]
+pub(crate) struct Snapshot {
+    frames: Vec<Frame>,
+    palette: Palette,
+}
+
+impl Snapshot {
+    /// Whether the canvas still looks like this, without taking another snapshot.
+    pub(crate) fn matches(&self, canvas: &Canvas) -> bool {
+        self.frames == canvas.frames && self.palette.colors() == canvas.palette.colors()
+    }
+}
+
+impl PartialEq for Snapshot {
+    fn eq(&self, other: &Snapshot) -> bool {
+        self.frames == other.frames && self.palette.colors() == other.palette.colors()
+    }
+}
+
+/// Largest canvas side, a full 32x32 tile BG.
+pub(crate) const MAX_CANVAS_SIZE: usize = 256;
+
+/// Whether a canvas can be `side` pixels wide or high: whole tiles, up to [`MAX_CANVAS_SIZE`].
+pub(crate) fn valid_size(side: usize) -> bool {
+    side > 0 && side <= MAX_CANVAS_SIZE && side.is_multiple_of(8)
+}
+
+/// A walk cycle at 10 frames of animation per second.
+pub(crate) const DEFAULT_FRAME_DURATION: u16 = 6;
+
+/// Neighboring frames drawn see-through over the current one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct OnionSkin {
+    pub enabled: bool,
+    /// 0 is invisible, 1 is opaque.
+    pub opacity: f32,
+    pub previous_tint: Color32,
+    pub next_tint: Color32,
+}
+
+impl Default for OnionSkin {
+    fn default() -> Self {
+        OnionSkin {
+            enabled: false,
+            opacity: 0.4,
+            previous_tint: Color32::from_rgb(255, 64, 64),
+            next_tint: Color32::from_rgb(64, 160, 255),
+        }
+    }
+}
+
+/// Mirror painting: every painted pixel is also painted reflected across the symmetry axis.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Symmetry {
+    #[default]
+    None,
+    /// Left to right, across a vertical line.
+    Horizontal,
+    /// Top to bottom, across a horizontal line.
+    Vertical,
+    FourWay,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 4] = [Symmetry::None, Symmetry::Horizontal, Symmetry::Vertical, Symmetry::FourWay];
+
+    fn mirrors_x(&self) -> bool {
+        matches!(self, Symmetry::Horizontal | Symmetry::FourWay)
+    }
+
+    fn mirrors_y(&self) -> bool {
+        matches!(self, Symmetry::Vertical | Symmetry::FourWay)
+    }
+}
+
+impl Display for Symmetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Symmetry::None => "Off",
+            Symmetry::Horizontal => "Horizontal",
+            Symmetry::Vertical => "Vertical",
+            Symmetry::FourWay => "Four-way",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+/// Ordered dithering between the primary and secondary colors. Patterns are anchored to canvas
+/// coordinates, so separate strokes line up with each other.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Dither {
+    #[default]
+    None,
+    Checker,
+    /// 4x4 Bayer matrix, with a density picking how much of it is the primary color.
+    Bayer,
+}
+
+/// Thresholds for [`Dither::Bayer`], 0-15.
+const BAYER_4X4: [[u8; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
+];
+
+impl Dither {
+    pub const ALL: [Dither; 3] = [Dither::None, Dither::Checker, Dither::Bayer];
+
+    /// Whether (x, y) gets the primary color. `density` is out of 16 and only matters for Bayer.
+    pub fn is_primary(&self, x: usize, y: usize, density: u8) -> bool {
+        match self {
+            Dither::None => true,
+            Dither::Checker => (x + y).is_multiple_of(2),
+            Dither::Bayer => BAYER_4X4[y % 4][x % 4] < density,
+        }
+    }
+}
+
+impl Display for Dither {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Dither::None => "Off",
+            Dither::Checker => "Checkerboard",
+            Dither::Bayer => "Bayer 4x4",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum BrushShape {
+    #[default]
+    Square,
+    Round,
+    /// Stamps a captured piece of the canvas.
+    Custom,
+}
+
+impl BrushShape {
+    pub const ALL: [BrushShape; 3] = [BrushShape::Square, BrushShape::Round, BrushShape::Custom];
+}
+
+impl Display for BrushShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            BrushShape::Square => "Square",
+            BrushShape::Round => "Round",
+            BrushShape::Custom => "Custom",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+pub(crate) struct Brush {
+    pub shape: BrushShape,
+    /// Width and height in pixels for square and round brushes.
+    pub size: usize,
+    /// Palette indices captured from a selection.
+    pub custom: Option<VecGrid>,
+    /// Whether custom brushes skip color 0 instead of stamping it.
+    pub transparent: bool,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Brush {
+            shape: BrushShape::default(),
+            size: 1,
+            custom: None,
+            transparent: true,
+        }
+    }
+}
+
+/// Pixels that were painted over, with their old colors: (x, y, old index).
+type Changes = Vec<(usize, usize, usize)>;
+
+/// Every pixel on the line from `a` to `b`, both ends included (Bresenham's).
+fn line(a: (usize, usize), b: (usize, usize)) -> Vec<(usize, usize)> {
+    let (mut x, mut y) = (a.0 as isize, a.1 as isize);
+    let (x1, y1) = (b.0 as isize, b.1 as isize);
+    let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+    let (sx, sy) = ((x1 - x).signum(), (y1 - y).signum());
+    let mut err = dx + dy;
+    let mut points = vec![];
+    loop {
+        points.push((x as usize, y as usize));
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+    points
+}
+
 pub(crate) struct Canvas {
     pub(crate) palette: Palette,
-    grid: Box<dyn Grid<usize>>,
+    /// Animation frames, all the same size and sharing the palette. Never empty.
+    frames: Vec<Frame>,
+    /// The frame being edited.
+    frame: usize,
     pos: Pos2,
     cursor: (usize, usize),
-    pixel_width: u32,
+    /// Zoom: screen pixels per canvas pixel.
+    pub(crate) pixel_width: u32,
     pub(crate) color_idx: usize,
+    pub(crate) onion_skin: OnionSkin,
+    pub(crate) symmetry: Symmetry,
+    /// Where the mirror lines are, in half pixels so they can go through the middle of a pixel
+    /// as well as between two. `None` is the middle of the canvas.
+    pub(crate) symmetry_axis: Option<(usize, usize)>,
+    pub(crate) dither: Dither,
+    /// The other color dithering mixes in. Picked by right clicking the palette.
+    pub(crate) secondary_idx: usize,
+    /// How many of every 16 pixels get the primary color with Bayer dithering.
+    pub(crate) dither_density: u8,
+    pub(crate) brush: Brush,
+    /// Corners of the selected rectangle: where the drag started and where it is now.
+    pub(crate) selection: Option<((usize, usize), (usize, usize))>,
+    /// Points of the mouse stroke in progress and what painting each one changed.
+    stroke: Vec<((usize, usize), Changes)>,
+    /// Whether strokes drop the corner pixels of L shapes, for clean one pixel lines.
+    pub(crate) pixel_perfect: bool,
 }
 
 impl Canvas {
     pub fn new() -> Canvas {
         Canvas {
             palette: Palette::default(),
-            grid: Box::new(CanvasGrid::<8, 8>::default()),
+            frames: vec![Frame::new(Box::new(CanvasGrid::<8, 8>::default()))],
+            frame: 0,
             pos: Pos2::new(0.0, 0.0),
             cursor: Default::default(),
             pixel_width: 20,
             color_idx: 0,
+            onion_skin: OnionSkin::default(),
+            symmetry: Symmetry::default(),
+            symmetry_axis: None,
+            dither: Dither::default(),
+            secondary_idx: 1,
+            dither_density: 8,
+            brush: Brush::default(),
+            selection: None,
+            stroke: vec![],
+            pixel_perfect: false,
         }
     }
 
-    pub(crate) fn set_size(&mut self, width: usize, height: usize) -> Result<(), Error> {
-        if width == self.grid.width() && height == self.grid.height() {
-            return Ok(());
+    pub(crate) fn from_parts(grid: Box<dyn Grid<usize>>, palette: Palette) -> Canvas {
+        Canvas {
+            palette,
+            frames: vec![Frame::new(grid)],
+            ..Canvas::new()
+        }
+    }
+
+    /// A canvas with several frames. Panics if there aren't any.
+    pub(crate) fn from_frames(frames: Vec<Frame>, palette: Palette) -> Canvas {
+        assert!(!frames.is_empty(), "a canvas needs at least one frame");
+        Canvas {
+            palette,
+            frames,
+            ..Canvas::new()
         }
+    }
 
-        let copy_width = Ord::min(self.grid.width(), width);
-        let copy_height = Ord::min(self.grid.height(), height);
-        match (width, height) {
-            (8, 8) => {
-                let mut grid = [[0usize;8];8];
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        Snapshot { frames: self.frames.clone(), palette: self.palette.clone() }
+    }
 
-                for i in 0..copy_width {
-                    for j in 0..copy_height {
-                        grid[j][i] = self.grid.get(i, j);
+    /// Puts the pixels and palette back how they were. The cursor and selection stay put, as
+    /// long as they still fit.
+    pub(crate) fn restore(&mut self, snapshot: Snapshot) {
+        self.frames = snapshot.frames;
+        self.palette = snapshot.palette;
+        self.frame = Ord::min(self.frame, self.frames.len() - 1);
+        self.color_idx = Ord::min(self.color_idx, self.palette.size() - 1);
+        self.set_cursor(self.cursor);
+        let (width, height) = (self.grid().width(), self.grid().height());
+        if self.selection.is_some_and(|(a, b)| Ord::max(a.0, b.0) >= width || Ord::max(a.1, b.1) >= height) {
+            self.selection = None;
+        }
+    }
+
+    /// The current frame's grid.
+    pub(crate) fn grid(&self) -> &dyn Grid<usize> {
+        self.frames[self.frame].grid.as_ref()
+    }
+
+    fn grid_mut(&mut self) -> &mut dyn Grid<usize> {
+        self.frames[self.frame].grid.as_mut()
+    }
+
+    pub(crate) fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub(crate) fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub(crate) fn set_frame(&mut self, frame: usize) {
+        self.frame = Ord::min(frame, self.frames.len() - 1);
+    }
+
+    pub(crate) fn frame_duration_mut(&mut self) -> &mut u16 {
+        &mut self.frames[self.frame].duration
+    }
+
+    /// Adds a blank frame after the current one and switches to it.
+    pub(crate) fn add_frame(&mut self) {
+        let mut frame = self.frames[self.frame].clone();
+        for x in 0..frame.grid.width() {
+            for y in 0..frame.grid.height() {
+                frame.grid.set(x, y, 0);
+            }
+        }
+        self.frame += 1;
+        self.frames.insert(self.frame, frame);
+    }
+
+    /// Adds a copy of the current frame after it and switches to it.
+    pub(crate) fn duplicate_frame(&mut self) {
+        let frame = self.frames[self.frame].clone();
+        self.frame += 1;
+        self.frames.insert(self.frame, frame);
+    }
+
+    /// Deletes the current frame, unless it's the only one.
+    pub(crate) fn delete_frame(&mut self) {
+        if self.frames.len() > 1 {
+            self.frames.remove(self.frame);
+            self.frame = Ord::min(self.frame, self.frames.len() - 1);
+        }
+    }
+
+    /// The symmetry axis in half pixels, (x, y).
+    pub(crate) fn symmetry_axis(&self) -> (usize, usize) {
+        self.symmetry_axis.unwrap_or((self.grid().width(), self.grid().height()))
+    }
+
+    /// Paints the brush at (x, y), and at its reflections if mirroring is on. Only mouse strokes
+    /// need to know what got painted over (to take back pixel perfect elbows), so everything
+    /// else uses this and skips the list [`Canvas::paint_recorded`] returns.
+    pub(crate) fn paint(&mut self, x: usize, y: usize) {
+        self.paint_recorded(x, y);
+    }
+
+    /// Continues the mouse stroke to (x, y). With pixel perfect on, a pixel that turns out to
+    /// be the elbow of an L between two diagonal neighbors is put back the way it was.
+    fn stroke_to(&mut self, point: (usize, usize)) {
+        let changes = self.paint_recorded(point.0, point.1);
+        self.stroke.push((point, changes));
+
+        let single_pixel = self.brush.shape != BrushShape::Custom && self.brush.size <= 1;
+        let n = self.stroke.len();
+        if !self.pixel_perfect || !single_pixel || n < 3 {
+            return;
+        }
+        let (a, b, c) = (self.stroke[n - 3].0, self.stroke[n - 2].0, self.stroke[n - 1].0);
+        let diagonal = a.0.abs_diff(c.0) == 1 && a.1.abs_diff(c.1) == 1;
+        let beside = |p: (usize, usize), q: (usize, usize)| p.0.abs_diff(q.0) + p.1.abs_diff(q.1) == 1;
+        if diagonal && beside(a, b) && beside(b, c) {
+            let (_, changes) = self.stroke.remove(n - 2);
+            for (x, y, old) in changes.into_iter().rev() {
+                self.grid_mut().set(x, y, old);
+            }
+        }
+    }
+
+    /// Same as [`Canvas::paint`], returning each pixel it set and what was there before.
+    fn paint_recorded(&mut self, x: usize, y: usize) -> Changes {
+        let (axis_x, axis_y) = self.symmetry_axis();
+        let (width, height) = (self.grid().width(), self.grid().height());
+        // reflecting across a line at axis/2 puts pixel p at axis - 1 - p
+        let mirror = |p: usize, axis: usize, size: usize| axis.checked_sub(p + 1).filter(|m| *m < size);
+
+        // mirror every pixel of the brush rather than its center, so uneven and custom brushes
+        // come out flipped
+        let mut points = self.brush_pixels(x, y);
+        if self.symmetry.mirrors_x() {
+            let reflected: Vec<_> = points.iter().filter_map(|&(px, py, idx)| mirror(px, axis_x, width).map(|mx| (mx, py, idx))).collect();
+            points.extend(reflected);
+        }
+        if self.symmetry.mirrors_y() {
+            let reflected: Vec<_> = points.iter().filter_map(|&(px, py, idx)| mirror(py, axis_y, height).map(|my| (px, my, idx))).collect();
+            points.extend(reflected);
+        }
+        let mut changes = Vec::with_capacity(points.len());
+        for (px, py, idx) in points {
+            changes.push((px, py, self.grid().get(px, py)));
+            match idx {
+                Some(idx) => self.grid_mut().set(px, py, idx),
+                None => self.plot(px, py),
+            }
+        }
+        changes
+    }
+
+    /// The pixels the brush covers when centered on (x, y), clipped to the canvas. Custom brushes
+    /// bring their own colors; the rest are `None` and get the current color.
+    fn brush_pixels(&self, x: usize, y: usize) -> Vec<(usize, usize, Option<usize>)> {
+        let (width, height) = (self.grid().width() as isize, self.grid().height() as isize);
+        let mut pixels = vec![];
+        let mut add = |dx: isize, dy: isize, idx: Option<usize>| {
+            let (px, py) = (x as isize + dx, y as isize + dy);
+            if (0..width).contains(&px) && (0..height).contains(&py) {
+                pixels.push((px as usize, py as usize, idx));
+            }
+        };
+
+        match (&self.brush.shape, &self.brush.custom) {
+            (BrushShape::Custom, Some(custom)) => {
+                let (w, h) = (custom.width() as isize, custom.height() as isize);
+                for by in 0..h {
+                    for bx in 0..w {
+                        let idx = custom.get(bx as usize, by as usize);
+                        if idx == 0 && self.brush.transparent {
+                            continue;
+                        }
+                        add(bx - w / 2, by - h / 2, Some(Ord::min(idx, self.palette.size() - 1)));
+                    }
+                }
+            }
+            (shape, _) => {
+                let size = self.brush.size.max(1) as isize;
+                let radius = size as f32 / 2.0;
+                for by in 0..size {
+                    for bx in 0..size {
+                        // distance from the brush's center to the middle of this pixel
+                        let (cx, cy) = (bx as f32 + 0.5 - radius, by as f32 + 0.5 - radius);
+                        if *shape == BrushShape::Round && cx * cx + cy * cy > radius * radius {
+                            continue;
+                        }
+                        add(bx - size / 2, by - size / 2, None);
                     }
                 }
+            }
+        }
+        pixels
+    }
+
+    /// The selected rectangle as (x, y, width, height).
+    pub(crate) fn selection_bounds(&self) -> Option<(usize, usize, usize, usize)> {
+        let ((ax, ay), (bx, by)) = self.selection?;
+        let (x, y) = (Ord::min(ax, bx), Ord::min(ay, by));
+        Some((x, y, ax.abs_diff(bx) + 1, ay.abs_diff(by) + 1))
+    }
 
-                self.grid = Box::new(CanvasGrid::<8, 8> { grid });
+    /// The selected pixels.
+    pub(crate) fn copy_selection(&self) -> Option<VecGrid> {
+        let (x, y, w, h) = self.selection_bounds()?;
+        let mut copy = VecGrid::new(w, h);
+        for cy in 0..h {
+            for cx in 0..w {
+                copy.set(cx, cy, self.grid().get(x + cx, y + cy));
+            }
+        }
+        Some(copy)
+    }
 
-                Ok(())
+    /// Sets the selected pixels to color 0.
+    pub(crate) fn clear_selection(&mut self) {
+        let Some((x, y, w, h)) = self.selection_bounds() else { return };
+        for cy in y..y + h {
+            for cx in x..x + w {
+                self.grid_mut().set(cx, cy, 0);
             }
-            (16, 16) => {
-                let mut grid = [[0usize;16];16];
+        }
+    }
 
-                for i in 0..copy_width {
-                    for j in 0..copy_height {
-                        grid[j][i] = self.grid.get(i, j);
-                    }
+    /// Copies pixels onto the canvas with their top left corner at `at`. Whatever hangs off the
+    /// edge is dropped, and colors past the end of the palette become its last color.
+    pub(crate) fn paste(&mut self, pixels: &dyn Grid<usize>, at: (usize, usize)) {
+        let (width, height) = (self.grid().width(), self.grid().height());
+        let last_color = self.palette.size() - 1;
+        for py in 0..pixels.height() {
+            for px in 0..pixels.width() {
+                let (x, y) = (at.0 + px, at.1 + py);
+                if x < width && y < height {
+                    self.grid_mut().set(x, y, Ord::min(pixels.get(px, py), last_color));
                 }
+            }
+        }
+    }
 
-                self.grid = Box::new(CanvasGrid::<16, 16> { grid });
+    /// Turns the selection into a custom brush and switches to it.
+    pub(crate) fn capture_brush(&mut self) {
+        if let Some(custom) = self.copy_selection() {
+            self.brush.custom = Some(custom);
+            self.brush.shape = BrushShape::Custom;
+        }
+    }
+
+    /// Sets one pixel to the current color, or the secondary one if the dither pattern says so.
+    fn plot(&mut self, x: usize, y: usize) {
+        let color_idx = if self.dither.is_primary(x, y, self.dither_density) {
+            self.color_idx
+        } else {
+            Ord::min(self.secondary_idx, self.palette.size() - 1)
+        };
+        self.grid_mut().set(x, y, color_idx);
+    }
 
-                Ok(())
+    /// Flood fills the area of one color around (x, y), four-way connected. With dithering on,
+    /// the area is filled with the pattern.
+    pub(crate) fn fill(&mut self, x: usize, y: usize) {
+        let (width, height) = (self.grid().width(), self.grid().height());
+        let target = self.grid().get(x, y);
+        let mut seen = vec![false; width * height];
+        let mut stack = vec![(x, y)];
+        seen[y * width + x] = true;
+        // collect first, since the fill color might be the same as what's being replaced
+        let mut area = vec![];
+        while let Some((px, py)) = stack.pop() {
+            area.push((px, py));
+            let neighbors = [
+                px.checked_sub(1).map(|nx| (nx, py)),
+                Some(px + 1).filter(|nx| *nx < width).map(|nx| (nx, py)),
+                py.checked_sub(1).map(|ny| (px, ny)),
+                Some(py + 1).filter(|ny| *ny < height).map(|ny| (px, ny)),
+            ];
+            for (nx, ny) in neighbors.into_iter().flatten() {
+                if !seen[ny * width + nx] && self.grid().get(nx, ny) == target {
+                    seen[ny * width + nx] = true;
+                    stack.push((nx, ny));
+                }
             }
-            (32, 32) => {
-                unimplemented!()
+        }
+        for (px, py) in area {
+            self.plot(px, py);
+        }
+    }
+
+    /// Which frame is showing `ticks` SNES frames into the looping animation.
+    pub(crate) fn frame_at(&self, ticks: u64) -> usize {
+        let total: u64 = self.frames.iter().map(|f| f.duration.max(1) as u64).sum();
+        let mut t = ticks % total;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let duration = frame.duration.max(1) as u64;
+            if t < duration {
+                return i;
             }
-            (64, 64) => {
-                unimplemented!()
+            t -= duration;
+        }
+        0
+    }
+
+    /// Switches the palette's bpp mode. Pixels using colors that no longer exist are set to
+    /// the new last color, the same way pasting clamps them.
+    pub(crate) fn set_bpp(&mut self, bpp: usize) -> Result<(), Error> {
+        self.palette.set_bpp(bpp)?;
+        let last_color = self.palette.size() - 1;
+        let clamp = |grid: &mut dyn Grid<usize>| {
+            for y in 0..grid.height() {
+                for x in 0..grid.width() {
+                    if grid.get(x, y) > last_color {
+                        grid.set(x, y, last_color);
+                    }
+                }
             }
-            _ => {
-                Err(Error::InvalidCanvasSize(width, height))
+        };
+        for frame in &mut self.frames {
+            clamp(frame.grid.as_mut());
+        }
+        if let Some(custom) = &mut self.brush.custom {
+            clamp(custom);
+        }
+        self.color_idx = Ord::min(self.color_idx, last_color);
+        self.secondary_idx = Ord::min(self.secondary_idx, last_color);
+        Ok(())
+    }
+
+    pub(crate) fn set_size(&mut self, width: usize, height: usize) -> Result<(), Error> {
+        if width == self.grid().width() && height == self.grid().height() {
+            return Ok(());
+        }
+
+        let mut resized = Vec::with_capacity(self.frames.len());
+        for frame in &self.frames {
+            let mut grid: Box<dyn Grid<usize>> = match (width, height) {
+                (8, 8) => Box::new(CanvasGrid::<8, 8>::new()),
+                (16, 16) => Box::new(CanvasGrid::<16, 16>::new()),
+                _ if valid_size(width) && valid_size(height) => Box::new(VecGrid::new(width, height)),
+                _ => {
+                    return Err(Error::InvalidCanvasSize(width, height));
+                }
+            };
+
+            let copy_width = Ord::min(frame.grid.width(), width);
+            let copy_height = Ord::min(frame.grid.height(), height);
+            for i in 0..copy_width {
+                for j in 0..copy_height {
+                    grid.set(i, j, frame.grid.get(i, j));
+                }
             }
+            resized.push(grid);
         }
+
+        for (frame, grid) in self.frames.iter_mut().zip(resized) {
+            frame.grid = grid;
+        }
+        self.selection = None;
+        Ok(())
+    }
+
+    /// Keyboard cursor position in pixels, (x, y).
+    pub(crate) fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    /// The pixel under a screen position, if it's on the canvas.
+    pub(crate) fn pixel_at(&self, pos: Pos2) -> Option<(usize, usize)> {
+        let idx = (pos - self.pos) / self.pixel_width as f32;
+        let inside = idx.x >= 0.0 && idx.y >= 0.0 && idx.x < self.grid().width() as f32 && idx.y < self.grid().height() as f32;
+        inside.then_some((idx.x as usize, idx.y as usize))
+    }
+
+    /// Moves the keyboard cursor, keeping it on the canvas.
+    pub(crate) fn set_cursor(&mut self, (x, y): (usize, usize)) {
+        self.cursor = (Ord::min(x, self.grid().width() - 1), Ord::min(y, self.grid().height() - 1));
+    }
+
+    pub(crate) fn pos(&self) -> Pos2 {
+        self.pos
     }
 
     pub(crate) fn set_pos(&mut self, pos: Pos2) {
@@ -316,7 +935,7 @@ impl Canvas {
 
     pub fn palette_pos(&self) -> Vec2 {
         Vec2 {
-            x: self.pos.x + self.grid.width() as f32 * self.pixel_width as f32,
+            x: self.pos.x + self.grid().width() as f32 * self.pixel_width as f32,
             y: self.pos.y,
         }
     }
@@ -327,18 +946,18 @@ impl Canvas {
 
     pub fn render(&self, ui: &mut Ui) {
         // render grid
-        for i in 0..self.grid.width() {
-            for j in 0..self.grid.height() {
+        for i in 0..self.grid().width() {
+            for j in 0..self.grid().height() {
                 ui.painter().add(RectShape {
                     rect: Rect {
                         min: (self.pos + Pos2::new(
                             i as f32 * self.pixel_width as f32,
                             j as f32 * self.pixel_width as f32,
-                        ).to_vec2()).into(),
+                        ).to_vec2()),
                         max: (self.pos + Pos2::new(
                             (i + 1) as f32 * self.pixel_width as f32,
                             (j + 1) as f32 * self.pixel_width as f32,
-                        ).to_vec2()).into(),
+                        ).to_vec2()),
                     },
                     rounding: Default::default(),
                     fill: self.get_pixel_color(i, j),
@@ -349,14 +968,43 @@ impl Canvas {
                 });
             }
         }
+        // render neighboring frames over the top
+        if self.onion_skin.enabled {
+            let previous = self.frame.checked_sub(1).map(|f| (f, self.onion_skin.previous_tint));
+            let next = Some(self.frame + 1).filter(|f| *f < self.frames.len()).map(|f| (f, self.onion_skin.next_tint));
+            for (frame, tint) in [previous, next].into_iter().flatten() {
+                self.render_onion_skin(ui, self.frames[frame].grid.as_ref(), tint);
+            }
+        }
+        // render mirror lines
+        if self.symmetry != Symmetry::None {
+            let (axis_x, axis_y) = self.symmetry_axis();
+            let pixel = self.pixel_width as f32;
+            let size = Vec2::new(self.grid().width() as f32, self.grid().height() as f32) * pixel;
+            let stroke = Stroke::new(2.0, Color32::LIGHT_BLUE);
+            if self.symmetry.mirrors_x() {
+                let x = self.pos.x + axis_x as f32 / 2.0 * pixel;
+                ui.painter().vline(x, self.pos.y..=self.pos.y + size.y, stroke);
+            }
+            if self.symmetry.mirrors_y() {
+                let y = self.pos.y + axis_y as f32 / 2.0 * pixel;
+                ui.painter().hline(self.pos.x..=self.pos.x + size.x, y, stroke);
+            }
+        }
+        // render selection
+        if let Some((x, y, w, h)) = self.selection_bounds() {
+            let pixel = self.pixel_width as f32;
+            let rect = Rect::from_min_size(self.pos + Vec2::new(x as f32, y as f32) * pixel, Vec2::new(w as f32, h as f32) * pixel);
+            ui.painter().rect_stroke(rect, 0.0, Stroke::new(2.0, Color32::WHITE));
+        }
         // render cursor
         let (x, y) = self.cursor;
-        let foo = self.pos + (Pos2::new(x as f32, y as f32) * self.pixel_width as f32).to_vec2();
+        let cursor_pos = self.pos + (Pos2::new(x as f32, y as f32) * self.pixel_width as f32).to_vec2();
 
         ui.painter().add(RectShape {
             rect: Rect {
-                min: foo,
-                max: foo + (Pos2::new(1.0, 1.0) * self.pixel_width as f32).to_vec2(),
+                min: cursor_pos,
+                max: cursor_pos + (Pos2::new(1.0, 1.0) * self.pixel_width as f32).to_vec2(),
             },
             rounding: Default::default(),
             fill: Color32::from(Rgba::from_black_alpha(0.0)),
@@ -397,26 +1045,66 @@ impl Canvas {
                 uv: Rect::ZERO,
             });
         }
+        // mark the secondary color when it's in use
+        if self.dither != Dither::None && self.secondary_idx < self.palette.size() {
+            let min = palette_pos.to_pos2() + Vec2::new(0.0, self.secondary_idx as f32 * self.pixel_width as f32);
+            let rect = Rect::from_min_size(min, Vec2::splat(self.pixel_width as f32)).shrink(3.0);
+            ui.painter().rect_stroke(rect, 0.0, Stroke::new(2.0, Color32::LIGHT_BLUE));
+        }
+    }
+
+    /// Draws a frame's non-transparent pixels half tinted and see-through.
+    fn render_onion_skin(&self, ui: &mut Ui, grid: &dyn Grid<usize>, tint: Color32) {
+        let pixel = self.pixel_width as f32;
+        let mix = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+        for i in 0..grid.width() {
+            for j in 0..grid.height() {
+                let idx = grid.get(i, j);
+                if idx == 0 {
+                    continue;
+                }
+                let color = self.palette.get_color(idx);
+                let color = Color32::from_rgb(mix(color.r(), tint.r()), mix(color.g(), tint.g()), mix(color.b(), tint.b()));
+                let min = self.pos + Vec2::new(i as f32, j as f32) * pixel;
+                ui.painter().rect_filled(
+                    Rect::from_min_size(min, Vec2::splat(pixel)),
+                    0.0,
+                    color.gamma_multiply(self.onion_skin.opacity),
+                );
+            }
+        }
+    }
+
+    /// Draws a frame small and without grid lines, for watching the animation play.
+    pub fn render_preview(&self, ui: &mut Ui, frame: usize) {
+        const SCALE: f32 = 4.0;
+        let grid = self.frames[frame].grid.as_ref();
+        let size = Vec2::new(grid.width() as f32, grid.height() as f32) * SCALE;
+        let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+        let painter = ui.painter_at(rect);
+        for i in 0..grid.width() {
+            for j in 0..grid.height() {
+                let min = rect.min + Vec2::new(i as f32, j as f32) * SCALE;
+                painter.rect_filled(Rect::from_min_size(min, Vec2::splat(SCALE)), 0.0, self.palette.get_color(grid.get(i, j)));
+            }
+        }
     }
 
-    pub fn update(&mut self, ui: &mut Ui) {
+    pub fn update(&mut self, ui: &mut Ui, keymap: &Keymap) {
         self.pos = ui.next_widget_position();
         // get area we're gonna draw in
         let draw_bounds = Rect {
             min: self.pos,
             max: (self.pos + Pos2 {
-                x: self.pixel_width as f32 * (self.grid.width() as f32 + 3.0),
-                y: self.pixel_width as f32 * self.grid.height() as f32,
-            }.to_vec2()).into(),
+                x: self.pixel_width as f32 * (self.grid().width() as f32 + 3.0),
+                y: self.pixel_width as f32 * self.grid().height() as f32,
+            }.to_vec2()),
         };
         ui.advance_cursor_after_rect(draw_bounds);
         ui.set_clip_rect(draw_bounds);
 
         // register click
-        if ui.input(|i| i.pointer.button_clicked(PointerButton::Primary)) {
-            let mut mouse_pos = Pos2::ZERO;
-            ui.input(|i| mouse_pos = i.pointer.interact_pos().unwrap());
-
+        if let Some(mouse_pos) = ui.input(|i| i.pointer.interact_pos().filter(|_| i.pointer.button_clicked(PointerButton::Primary))) {
             // select palette
             let idx = (mouse_pos - self.palette_pos()) / self.pixel_width as f32;
             let x_bounds = idx.x <= 1.0 && idx.x >= 0.0;
@@ -424,64 +1112,99 @@ impl Canvas {
             if x_bounds && y_bounds {
                 self.color_idx = idx.y as usize;
             }
+
+            // shift+click fills
+            let idx = (mouse_pos - self.pos) / self.pixel_width as f32;
+            let x_bounds = idx.x < self.grid().width() as f32 && idx.x >= 0.0;
+            let y_bounds = idx.y < self.grid().height() as f32 && idx.y >= 0.0;
+            if x_bounds && y_bounds && ui.input(|i| i.modifiers.shift) {
+                self.fill(idx.x as usize, idx.y as usize);
+            }
+        }
+        if ui.input(|i| i.pointer.button_clicked(PointerButton::Secondary)) {
+            if let Some(mouse_pos) = ui.input(|i| i.pointer.interact_pos()) {
+                // select secondary color
+                let idx = (mouse_pos - self.palette_pos()) / self.pixel_width as f32;
+                let x_bounds = idx.x <= 1.0 && idx.x >= 0.0;
+                let y_bounds = idx.y < self.palette.size() as f32 && idx.y >= 0.0;
+                if x_bounds && y_bounds {
+                    self.secondary_idx = idx.y as usize;
+                }
+            }
         }
 
-        // register button held down
-        if ui.input(|i| i.pointer.button_down(PointerButton::Primary)) {
-            let mut mouse_pos = Pos2::ZERO;
-            ui.input(|i| mouse_pos = i.pointer.interact_pos().unwrap());
+        // ctrl+drag selects
+        if ui.input(|i| i.pointer.button_down(PointerButton::Primary) && i.modifiers.command) {
+            if let Some(mouse_pos) = ui.input(|i| i.pointer.interact_pos()) {
+                let idx = (mouse_pos - self.pos) / self.pixel_width as f32;
+                let x = (idx.x.max(0.0) as usize).min(self.grid().width() - 1);
+                let y = (idx.y.max(0.0) as usize).min(self.grid().height() - 1);
+                let inside = idx.x >= 0.0 && idx.y >= 0.0 && idx.x < self.grid().width() as f32 && idx.y < self.grid().height() as f32;
+                if ui.input(|i| i.pointer.button_pressed(PointerButton::Primary)) {
+                    self.selection = inside.then_some(((x, y), (x, y)));
+                } else if let Some((_, corner)) = &mut self.selection {
+                    *corner = (x, y);
+                }
+            }
+        }
+        if ui.input_mut(|i| i.consume_shortcut(&keymap.get(Action::CaptureBrush))) {
+            self.capture_brush();
+        }
 
+        // register button held down
+        let held = |i: &InputState| i.pointer.button_down(PointerButton::Primary) && !i.modifiers.shift && !i.modifiers.command;
+        if let Some(mouse_pos) = ui.input(|i| i.pointer.interact_pos().filter(|_| held(i))) {
             // paint on canvas
             let idx = (mouse_pos - self.pos) / self.pixel_width as f32;
-            let x_bounds = idx.x < self.grid.width() as f32 && idx.x >= 0.0;
-            let y_bounds = idx.y < self.grid.height() as f32 && idx.y >= 0.0;
+            let x_bounds = idx.x < self.grid().width() as f32 && idx.x >= 0.0;
+            let y_bounds = idx.y < self.grid().height() as f32 && idx.y >= 0.0;
             if x_bounds && y_bounds {
-                self.grid.set(idx.x as usize, idx.y as usize, self.color_idx);
+                let point = (idx.x as usize, idx.y as usize);
+                // fill in whatever the pointer skipped over since last frame
+                match self.stroke.last() {
+                    Some((last, _)) if *last == point => {}
+                    Some((last, _)) => {
+                        for p in line(*last, point).into_iter().skip(1) {
+                            self.stroke_to(p);
+                        }
+                    }
+                    None => self.stroke_to(point),
+                }
+            } else {
+                // coming back in somewhere else shouldn't draw a line across the canvas
+                self.stroke.clear();
             }
+        } else {
+            self.stroke.clear();
         }
 
         // switch palette
-        if ui.input_mut(|i| i.consume_shortcut(&crate::app::shortcut::PALETTE_FORWARD)) {
+        if ui.input_mut(|i| i.consume_shortcut(&keymap.get(Action::PaletteForward))) {
             self.color_idx += 1;
             if self.color_idx == self.palette.size() {
                 self.color_idx = 0;
             }
         }
-        if ui.input_mut(|i| i.consume_shortcut(&crate::app::shortcut::PALETTE_BACKWARD)) {
+        if ui.input_mut(|i| i.consume_shortcut(&keymap.get(Action::PaletteBackward))) {
             if self.color_idx == 0 {
                 self.color_idx = self.palette.size();
             }
             self.color_idx -= 1;
         }
 
-        // move cursor
-        if ui.input_mut(|mut i| i.consume_shortcut(&action::CURSOR_LEFT)) {
-            self.cursor.0 += 1;
-            if self.cursor.0 >= self.grid.width() {
-                self.cursor.0 = 0;
-            }
-        }
-        if ui.input_mut(|mut i| i.consume_shortcut(&action::CURSOR_RIGHT)) {
-            if self.cursor.0 == 0 {
-                self.cursor.0 = self.grid.width();
-            }
-            self.cursor.0 -= 1;
-        }
-        if ui.input_mut(|mut i| i.consume_shortcut(&action::CURSOR_UP)) {
-            if self.cursor.1 == 0 {
-                self.cursor.1 = self.grid.width();
-            }
-            self.cursor.1 -= 1;
-        }
-        if ui.input_mut(|mut i| i.consume_shortcut(&action::CURSOR_DOWN)) {
-            self.cursor.1 += 1;
-            if self.cursor.1 >= self.grid.width() {
-                self.cursor.1 = 0;
-            }
+        if ui.input_mut(|i| i.consume_shortcut(&keymap.get(Action::OnionSkin))) {
+            self.onion_skin.enabled = !self.onion_skin.enabled;
         }
+
         // paint with cursor
-        if ui.input_mut(|mut i| i.key_down(action::CURSOR_PAINT.logical_key)) {
-            self.grid.set(self.cursor.0, self.cursor.1, self.color_idx);
+        let paint = keymap.get(Action::CursorPaint);
+        if ui.input(|i| i.key_down(paint.logical_key) && i.modifiers == paint.modifiers) {
+            let (x, y) = self.cursor;
+            self.paint(x, y);
+        }
+        if ui.input_mut(|i| i.consume_shortcut(&keymap.get(Action::CursorFill))) {
+            let (x, y) = self.cursor;
+            self.fill(x, y);
         }
 
         // reset draw bounds
@@ -489,12 +1212,24 @@ impl Canvas {
     }
 
     pub fn get_pixel_color(&self, row: usize, col: usize) -> Color32 {
-        self.palette.get_color(self.grid.get(row, col))
+        self.palette.get_color(self.grid().get(row, col))
+    }
+
+    pub fn serialize_ordered(&self, order: TileOrder) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        serde::write_out_ordered(self.grid(), &self.palette, order)
+    }
+
+    /// Every frame's tiles one after another, then the palette.
+    pub fn serialize_frames(&self, order: TileOrder) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let mut v_ram = vec![];
+        for frame in &self.frames {
+            v_ram.extend(serde::write_out_ordered(frame.grid.as_ref(), &self.palette, order)?.0);
+        }
+        Ok((v_ram, serde::write_palette(&self.palette)))
     }
 
-    // NOTE: function signature will change soon to implement file IO
-    pub fn serialize(&self) -> (Vec<u8>, Vec<u8>) {
-        serde::write_out(self.grid.as_ref(), &self.palette)
+    pub fn serialize_tilemap(&self, palette_slot: u8, order: TileOrder) -> Vec<u8> {
+        serde::write_tilemap(self.grid(), palette_slot, order)
     }
 }
 
@@ -503,3 +1238,74 @@ impl Default for Canvas {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each step of a line moves to one of the 8 neighbors.
+    fn assert_connected(points: &[(usize, usize)]) {
+        for pair in points.windows(2) {
+            let (p, q) = (pair[0], pair[1]);
+            assert!(p != q && p.0.abs_diff(q.0) <= 1 && p.1.abs_diff(q.1) <= 1, "{p:?} to {q:?}");
+        }
+    }
+
+    #[test]
+    fn test_line() {
+        assert_eq!(line((2, 2), (2, 2)), [(2, 2)]);
+        assert_eq!(line((0, 0), (3, 0)), [(0, 0), (1, 0), (2, 0), (3, 0)]);
+
+        // steep, shallow, and going back up or left
+        for (a, b) in [((1, 0), (3, 9)), ((0, 5), (9, 2)), ((8, 8), (1, 6)), ((6, 9), (4, 0))] {
+            let points = line(a, b);
+            assert_eq!(points.first(), Some(&a));
+            assert_eq!(points.last(), Some(&b));
+            assert_eq!(points.len(), a.0.abs_diff(b.0).max(a.1.abs_diff(b.1)) + 1);
+            assert_connected(&points);
+        }
+    }
+
+    #[test]
+    fn test_pixel_perfect_stroke() {
+        let mut canvas = Canvas::new();
+        canvas.pixel_perfect = true;
+        canvas.color_idx = 2;
+        canvas.paint(1, 0);
+
+        // (1, 0) is the elbow between (0, 0) and (1, 1), so it goes back to 2
+        canvas.color_idx = 1;
+        for point in [(0, 0), (1, 0), (1, 1), (2, 1)] {
+            canvas.stroke_to(point);
+        }
+        let grid = canvas.grid();
+        assert_eq!(grid.get(0, 0), 1);
+        assert_eq!(grid.get(1, 0), 2);
+        assert_eq!(grid.get(1, 1), 1);
+        // a straight run isn't an elbow
+        assert_eq!(grid.get(2, 1), 1);
+
+        canvas.stroke.clear();
+        canvas.pixel_perfect = false;
+        for point in [(5, 5), (6, 5), (6, 6)] {
+            canvas.stroke_to(point);
+        }
+        assert_eq!(canvas.grid().get(6, 5), 1);
+    }
+
+    #[test]
+    fn test_set_bpp_clamps_pixels() {
+        let mut canvas = Canvas::new();
+        canvas.set_bpp(4).unwrap();
+        canvas.color_idx = 9;
+        canvas.paint(0, 0);
+        canvas.color_idx = 2;
+        canvas.paint(1, 0);
+        canvas.color_idx = 12;
+
+        canvas.set_bpp(2).unwrap();
+        assert_eq!(canvas.grid().get(0, 0), 3);
+        assert_eq!(canvas.grid().get(1, 0), 2);
+        assert_eq!(canvas.color_idx, 3);
+    }
+}
@@ -0,0 +1,243 @@
+//! Autosave and crash recovery.
+//!
+//! While the app runs, every open document is saved every [`AUTOSAVE_SECONDS`] (if any changed)
+//! to a recovery directory, `~/.local/share/snes-paint/recovery` on Linux, as ordinary project
+//! files plus a list of where the projects live. Each running instance gets its own directory in
+//! there, with a lock file it holds locked until the window closes normally and the directory is
+//! removed. A directory whose lock nobody holds is left from a session that crashed, so its
+//! autosave gets offered back.
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+use crate::document::Document;
+use crate::paint::Canvas;
+use crate::serde::project;
+use crate::Error;
+
+pub const AUTOSAVE_SECONDS: f64 = 30.0;
+const LOCK: &str = "session.lock";
+const SESSION: &str = "session.toml";
+
+/// One document an autosave brings back.
+pub(crate) struct Session {
+    pub canvas: Canvas,
+    /// Where the project was saved, if it ever was.
+    pub project_path: Option<PathBuf>,
+}
+
+/// The autosaved project for the `n`th tab.
+fn canvas_file(n: usize) -> String {
+    format!("session-{n}.snesp")
+}
+
+pub fn dir() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|dir| dir.join("snes-paint").join("recovery"))
+}
+
+pub fn write_session(dir: &Path, documents: &[&Document]) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+    let mut list = vec![];
+    for (n, doc) in documents.iter().enumerate() {
+        let mut entry = Table::new();
+        if let Some(path) = &doc.path {
+            entry.insert("project_path".to_owned(), Value::String(path.to_string_lossy().into_owned()));
+        }
+        list.push(Value::Table(entry));
+        // write to the side and rename, so a crash mid-save doesn't leave half a file
+        let temp = dir.join(format!("{}.tmp", canvas_file(n)));
+        fs::write(&temp, project::write_project(&doc.canvas))?;
+        fs::rename(temp, dir.join(canvas_file(n)))?;
+    }
+    let mut session = Table::new();
+    session.insert("document".to_owned(), Value::Array(list));
+    fs::write(dir.join(SESSION), session.to_string())?;
+    // tabs closed since the last autosave
+    remove_canvases(dir, documents.len())
+}
+
+/// Every document in the autosave, in tab order.
+pub fn read_session(dir: &Path) -> Result<Vec<Session>, Error> {
+    let table: Table = fs::read_to_string(dir.join(SESSION))?
+        .parse()
+        .map_err(|e| Error::InvalidProjectFile(format!("{SESSION}: {e}")))?;
+    let entries = table.get("document").and_then(Value::as_array).cloned().unwrap_or_default();
+    entries.iter().enumerate().map(|(n, entry)| {
+        let canvas = project::read_project(&fs::read_to_string(dir.join(canvas_file(n)))?)?;
+        let project_path = entry.get("project_path").and_then(Value::as_str).map(PathBuf::from);
+        Ok(Session { canvas, project_path })
+    }).collect()
+}
+
+/// Deletes the autosaved projects from the `from`th on.
+fn remove_canvases(dir: &Path, from: usize) -> Result<(), Error> {
+    for n in from.. {
+        match fs::remove_file(dir.join(canvas_file(n))) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e.into()),
+            Ok(()) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Locks a session directory's lock file, or None if another running instance has it.
+fn take_lock(dir: &Path) -> Result<Option<File>, Error> {
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(dir.join(LOCK))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Keeps this instance's recovery directory up to date for one run of the app.
+#[derive(Default)]
+pub(crate) struct Autosave {
+    dir: Option<PathBuf>,
+    /// Held for as long as the session runs. The OS lets go of it if the app crashes.
+    lock: Option<File>,
+    /// The last project texts and paths written, to skip saving when nothing changed.
+    last_written: String,
+    /// App time of the last check, in seconds.
+    last_check: f64,
+}
+
+impl Autosave {
+    /// Marks a session as running in its own directory under `base`. Takes over the directory of
+    /// a session that didn't close cleanly if there is one, and returns what it left behind.
+    pub fn start(base: PathBuf) -> Result<(Autosave, Option<Vec<Session>>), Error> {
+        fs::create_dir_all(&base)?;
+        let mut dirs: Vec<PathBuf> = fs::read_dir(&base)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| path.is_dir())
+            .collect();
+        dirs.sort();
+
+        let mut found = None;
+        for dir in dirs {
+            if let Some(lock) = take_lock(&dir)? {
+                found = Some((dir, lock));
+                break;
+            }
+        }
+        let (dir, mut lock, session) = match found {
+            Some((dir, lock)) => {
+                // an autosave too broken to read is no reason to stop autosaving
+                let session = read_session(&dir).ok().filter(|documents| !documents.is_empty());
+                (dir, lock, session)
+            }
+            // every other directory belongs to a running instance
+            None => {
+                let pid = std::process::id();
+                let mut n = 0;
+                loop {
+                    let dir = base.join(if n == 0 { pid.to_string() } else { format!("{pid}-{n}") });
+                    fs::create_dir_all(&dir)?;
+                    if let Some(lock) = take_lock(&dir)? {
+                        break (dir, lock, None);
+                    }
+                    n += 1;
+                }
+            }
+        };
+        // only for people poking around in the directory
+        lock.set_len(0)?;
+        write!(lock, "{}", std::process::id())?;
+        Ok((Autosave { dir: Some(dir), lock: Some(lock), ..Default::default() }, session))
+    }
+
+    /// Saves the documents if it's been long enough and any of them changed.
+    pub fn update(&mut self, time: f64, documents: &[&Document]) -> Result<(), Error> {
+        let Some(dir) = &self.dir else { return Ok(()) };
+        if time - self.last_check < AUTOSAVE_SECONDS {
+            return Ok(());
+        }
+        self.last_check = time;
+        let text: String = documents.iter()
+            .map(|doc| format!("{:?}\n{}", doc.path, project::write_project(&doc.canvas)))
+            .collect();
+        if text != self.last_written {
+            write_session(dir, documents)?;
+            self.last_written = text;
+        }
+        Ok(())
+    }
+
+    /// Throws away what a crashed session left, when the user doesn't want it back.
+    pub fn discard(&mut self) -> Result<(), Error> {
+        self.remove(&[SESSION])
+    }
+
+    /// Clean exit: nothing to recover next time.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.remove(&[SESSION])?;
+        // some platforms won't delete a locked file
+        self.lock = None;
+        self.remove(&[LOCK])?;
+        if let Some(dir) = self.dir.take() {
+            fs::remove_dir(dir)?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, files: &[&str]) -> Result<(), Error> {
+        let Some(dir) = &self.dir else { return Ok(()) };
+        remove_canvases(dir, 0)?;
+        for file in files {
+            match fs::remove_file(dir.join(file)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crash_then_restore() {
+        let dir = std::env::temp_dir().join(format!("snes-paint-recovery-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut canvas = Canvas::new();
+        canvas.color_idx = 3;
+        canvas.paint(2, 1);
+        let art = Document::new(canvas, Some(PathBuf::from("art.snesp")));
+        let untitled = Document::default();
+        let (mut autosave, leftover) = Autosave::start(dir.clone()).unwrap();
+        assert!(leftover.is_none());
+        autosave.update(AUTOSAVE_SECONDS, &[&art, &untitled, &untitled]).unwrap();
+        // closing a tab leaves its autosave behind otherwise
+        autosave.update(2.0 * AUTOSAVE_SECONDS, &[&untitled, &art]).unwrap();
+        let instance = dir.join(std::process::id().to_string());
+        assert!(instance.join(canvas_file(1)).exists());
+        assert!(!instance.join(canvas_file(2)).exists());
+
+        // another instance running alongside doesn't take it for a crash
+        let (mut other, leftover) = Autosave::start(dir.clone()).unwrap();
+        assert!(leftover.is_none());
+        assert_ne!(other.dir.as_deref(), Some(instance.as_path()));
+        other.finish().unwrap();
+
+        // no finish(), as if it crashed
+        drop(autosave);
+        let (mut autosave, leftover) = Autosave::start(dir.clone()).unwrap();
+        let session = leftover.unwrap();
+        assert_eq!(session.len(), 2);
+        assert_eq!(session[0].project_path, None);
+        assert_eq!(session[1].canvas.grid().get(2, 1), 3);
+        assert_eq!(session[1].project_path.as_deref(), Some(Path::new("art.snesp")));
+
+        autosave.finish().unwrap();
+        assert!(!instance.exists());
+        let (_, leftover) = Autosave::start(dir.clone()).unwrap();
+        assert!(leftover.is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,349 @@
+//! ROM images: copier headers, the internal header, and mapping SNES addresses to file offsets.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::serde;
+use crate::Error;
+
+pub mod patch;
+
+/// Size of the header some copiers stuck onto the front of dumps (usually .smc files).
+const COPIER_HEADER_SIZE: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Mapping {
+    /// 32 KiB banks mapped at $8000-$FFFF. Internal header at $7FC0 in the file.
+    LoRom,
+    /// 64 KiB banks mapped at $C0-$FF:0000-FFFF. Internal header at $FFC0 in the file.
+    HiRom,
+}
+
+impl Mapping {
+    fn header_offset(&self) -> usize {
+        match self {
+            Mapping::LoRom => 0x7fc0,
+            Mapping::HiRom => 0xffc0,
+        }
+    }
+}
+
+impl std::fmt::Display for Mapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Mapping::LoRom => "LoROM",
+            Mapping::HiRom => "HiROM",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+pub(crate) struct Rom {
+    /// Where the ROM was opened from, if it came from a file.
+    path: Option<PathBuf>,
+    /// The file as it was opened, for making patches against.
+    original: Vec<u8>,
+    /// The ROM itself, without any copier header.
+    data: Vec<u8>,
+    copier_header: Option<Vec<u8>>,
+    mapping: Mapping,
+}
+
+impl Rom {
+    pub fn load(path: &Path) -> Result<Rom, Error> {
+        let mut rom = Rom::from_bytes(fs::read(path)?)?;
+        rom.path = Some(path.to_owned());
+        Ok(rom)
+    }
+
+    pub fn from_bytes(mut data: Vec<u8>) -> Result<Rom, Error> {
+        let original = data.clone();
+        // ROMs come in multiples of 1 KiB, so 512 left over means there's a copier header
+        let copier_header = if data.len() % 1024 == COPIER_HEADER_SIZE {
+            Some(data.drain(..COPIER_HEADER_SIZE).collect())
+        } else {
+            None
+        };
+        if data.len() < 0x8000 {
+            return Err(Error::InvalidRom(format!("{} bytes is too small for a ROM", data.len())));
+        }
+
+        let lo = header_score(&data, Mapping::LoRom);
+        let hi = header_score(&data, Mapping::HiRom);
+        let mapping = if hi > lo { Mapping::HiRom } else { Mapping::LoRom };
+        header_start(&data, mapping)?;
+        Ok(Rom { path: None, original, data, copier_header, mapping })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The whole file, copier header included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.copier_header.clone().unwrap_or_default();
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.to_bytes() != self.original
+    }
+
+    /// Returns: the ROM's changes since it was opened, either as the whole file or as a patch.
+    pub fn export(&self, format: patch::PatchFormat) -> Result<Vec<u8>, Error> {
+        format.write(&self.original, &self.to_bytes())
+    }
+
+    /// Marks the current contents as what's on disk, so later patches only hold later changes.
+    pub fn mark_saved(&mut self) {
+        self.original = self.to_bytes();
+    }
+
+    /// Overwrites a region's tiles with re-encoded ones and fixes the checksum. The new data has
+    /// to be exactly as long as what was read, so nothing past the region gets clobbered.
+    pub fn write_tiles(&mut self, region: &Region, tiles: &[u8]) -> Result<(), Error> {
+        if tiles.len() != region.len() {
+            return Err(Error::RomSizeMismatch(region.len(), tiles.len()));
+        }
+        let Some(dest) = self.data.get_mut(region.offset..region.offset + region.len()) else {
+            return Err(Error::InvalidRom(format!("region at {:X} runs past the end of the ROM", region.offset)));
+        };
+        dest.copy_from_slice(tiles);
+        self.fix_checksum()
+    }
+
+    /// Sum of every byte in the ROM. Sizes that aren't a power of two are summed as if the part
+    /// past the largest power of two was mirrored up to fill it, same as the cartridge does.
+    pub fn checksum(&self) -> u16 {
+        let sum = |bytes: &[u8]| bytes.iter().fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+        let len = self.data.len();
+        let base = if len.is_power_of_two() { len } else { len.next_power_of_two() / 2 };
+        let (first, rest) = self.data.split_at(base);
+        let mut checksum = sum(first);
+        if !rest.is_empty() {
+            let repeats = (base / rest.len()) as u16;
+            checksum = checksum.wrapping_add(sum(rest).wrapping_mul(repeats));
+        }
+        checksum
+    }
+
+    /// Recomputes the checksum and its complement in the internal header.
+    pub fn fix_checksum(&mut self) -> Result<(), Error> {
+        let start = header_start(&self.data, self.mapping)?;
+        // the checksum covers itself, so start from a checksum/complement pair that always sums
+        // to the same thing
+        self.data[start + 0x1c..start + 0x20].copy_from_slice(&[0xff, 0xff, 0x00, 0x00]);
+        let checksum = self.checksum();
+        self.data[start + 0x1c..start + 0x1e].copy_from_slice(&(!checksum).to_le_bytes());
+        self.data[start + 0x1e..start + 0x20].copy_from_slice(&checksum.to_le_bytes());
+        Ok(())
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn mapping(&self) -> Mapping {
+        self.mapping
+    }
+
+    /// Fails if the ROM is too small to have that mapping's internal header.
+    pub fn set_mapping(&mut self, mapping: Mapping) -> Result<(), Error> {
+        header_start(&self.data, mapping)?;
+        self.mapping = mapping;
+        Ok(())
+    }
+
+    pub fn has_copier_header(&self) -> bool {
+        self.copier_header.is_some()
+    }
+
+    /// The game title from the internal header, trimmed.
+    pub fn title(&self) -> String {
+        let start = self.mapping.header_offset();
+        self.data.get(start..start + 21)
+            .map(|t| t.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '?' }).collect::<String>())
+            .unwrap_or_default()
+            .trim_end()
+            .to_owned()
+    }
+
+    /// Converts a 24 bit SNES address (bank in the top byte) to an offset into [`Rom::data`].
+    pub fn snes_to_pc(&self, address: u32) -> Option<usize> {
+        let bank = (address >> 16) as usize & 0xff;
+        let addr = address as usize & 0xffff;
+        let offset = match self.mapping {
+            Mapping::LoRom => {
+                // WRAM and hardware registers live in the bottom half of every bank
+                if addr < 0x8000 || matches!(bank, 0x7e | 0x7f) {
+                    return None;
+                }
+                (bank & 0x7f) * 0x8000 + (addr - 0x8000)
+            }
+            Mapping::HiRom => match bank {
+                0xc0..=0xff | 0x40..=0x7d => (bank & 0x3f) << 16 | addr,
+                0x00..=0x3f | 0x80..=0xbf if addr >= 0x8000 => (bank & 0x3f) << 16 | addr,
+                _ => return None,
+            },
+        };
+        (offset < self.data.len()).then_some(offset)
+    }
+
+    /// Converts an offset into [`Rom::data`] to the SNES address games usually use for it
+    /// (banks $80+ for LoROM, $C0+ for HiROM).
+    pub fn pc_to_snes(&self, offset: usize) -> u32 {
+        let address = match self.mapping {
+            Mapping::LoRom => 0x80_0000 | (offset / 0x8000) << 16 | (offset % 0x8000 + 0x8000),
+            Mapping::HiRom => 0xc0_0000 | offset,
+        };
+        address as u32 & 0xff_ffff
+    }
+
+    /// `len` bytes starting at a file offset, if they're all inside the ROM.
+    pub fn read(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        self.data.get(offset..offset.checked_add(len)?)
+    }
+}
+
+/// A run of tiles decoded out of a ROM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Region {
+    /// File offset of the first tile, not counting any copier header.
+    pub offset: usize,
+    pub bpp: usize,
+    /// How many tiles across the canvas showed them.
+    pub tiles_wide: usize,
+    pub tiles: usize,
+}
+
+impl Region {
+    /// Length in bytes.
+    pub fn len(&self) -> usize {
+        self.tiles * serde::tile_size(self.bpp)
+    }
+
+    /// Size of the canvas the tiles were decoded into, with the last row padded out.
+    pub fn canvas_size(&self) -> (usize, usize) {
+        (self.tiles_wide * 8, self.tiles.div_ceil(self.tiles_wide).max(1) * 8)
+    }
+}
+
+/// Where a mapping's internal header starts, if the whole header fits in `data`.
+fn header_start(data: &[u8], mapping: Mapping) -> Result<usize, Error> {
+    let start = mapping.header_offset();
+    if start + 0x40 > data.len() {
+        return Err(Error::InvalidRom(format!("{} bytes is too small for a {mapping} header", data.len())));
+    }
+    Ok(start)
+}
+
+/// How much the bytes at a mapping's header location look like a real internal header.
+fn header_score(data: &[u8], mapping: Mapping) -> i32 {
+    let start = mapping.header_offset();
+    let Some(header) = data.get(start..start + 0x40) else {
+        return i32::MIN;
+    };
+    let mut score = 0;
+
+    // map mode: $20/$30 for LoROM, $21/$31 for HiROM (fast/slow), plus the ExHiROM/SA-1 odd ones
+    let map_mode = header[0x15] & 0xef;
+    score += match (mapping, map_mode) {
+        (Mapping::LoRom, 0x20 | 0x22 | 0x23) => 4,
+        (Mapping::HiRom, 0x21 | 0x25) => 4,
+        _ => 0,
+    };
+    let checksum = u16::from_le_bytes([header[0x1e], header[0x1f]]);
+    let complement = u16::from_le_bytes([header[0x1c], header[0x1d]]);
+    if checksum ^ complement == 0xffff {
+        score += 4;
+    }
+    if header[..21].iter().all(|b| (0x20..0x7f).contains(b)) {
+        score += 2;
+    }
+    // the reset vector has to point into ROM
+    let reset = u16::from_le_bytes([header[0x3c], header[0x3d]]);
+    if reset >= 0x8000 {
+        score += 1;
+    }
+    score
+}
+
+/// Parses a hex address, with or without `$`/`0x` and a `:` between bank and address.
+pub(crate) fn parse_address(text: &str) -> Option<u32> {
+    let text = text.trim();
+    let text = text.strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text)
+        .replace(':', "");
+    u32::from_str_radix(&text, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_rom(mapping: Mapping, size: usize) -> Vec<u8> {
+        let mut data = vec![0u8; size];
+        let header = &mut data[mapping.header_offset()..];
+        header[..21].copy_from_slice(b"TEST GAME            ");
+        header[0x15] = if mapping == Mapping::LoRom { 0x20 } else { 0x21 };
+        header[0x1c..0x20].copy_from_slice(&[0xff, 0xff, 0x00, 0x00]);
+        header[0x3c..0x3e].copy_from_slice(&[0x00, 0x80]);
+        data
+    }
+
+    #[test]
+    fn test_detects_mapping_and_copier_header() {
+        let lo = Rom::from_bytes(fake_rom(Mapping::LoRom, 0x20000)).unwrap();
+        assert_eq!(lo.mapping(), Mapping::LoRom);
+        assert_eq!(lo.title(), "TEST GAME");
+        assert!(!lo.has_copier_header());
+
+        let mut headered = vec![0u8; 512];
+        headered.extend(fake_rom(Mapping::HiRom, 0x20000));
+        let hi = Rom::from_bytes(headered).unwrap();
+        assert_eq!(hi.mapping(), Mapping::HiRom);
+        assert!(hi.has_copier_header());
+        assert_eq!(hi.data().len(), 0x20000);
+
+        // a HiROM header would be past the end of a 32 KiB ROM
+        let mut small = Rom::from_bytes(fake_rom(Mapping::LoRom, 0x8000)).unwrap();
+        assert!(small.set_mapping(Mapping::HiRom).is_err());
+        assert_eq!(small.mapping(), Mapping::LoRom);
+        small.fix_checksum().unwrap();
+    }
+
+    #[test]
+    fn test_address_mapping() {
+        let lo = Rom::from_bytes(fake_rom(Mapping::LoRom, 0x20000)).unwrap();
+        assert_eq!(lo.snes_to_pc(0x808000), Some(0));
+        assert_eq!(lo.snes_to_pc(0x018123), Some(0x8123));
+        assert_eq!(lo.snes_to_pc(0x800000), None);
+        assert_eq!(lo.pc_to_snes(0x8123), 0x818123);
+
+        let hi = Rom::from_bytes(fake_rom(Mapping::HiRom, 0x20000)).unwrap();
+        assert_eq!(hi.snes_to_pc(0xc12345), Some(0x12345));
+        assert_eq!(hi.snes_to_pc(0x01_8000), Some(0x18000));
+        assert_eq!(hi.pc_to_snes(0x12345), 0xc12345);
+        assert_eq!(parse_address("$C1:2345"), Some(0xc12345));
+    }
+
+    #[test]
+    fn test_write_tiles_fixes_checksum() {
+        let mut rom = Rom::from_bytes(fake_rom(Mapping::LoRom, 0x18000)).unwrap();
+        let region = Region { offset: 0x100, bpp: 2, tiles_wide: 1, tiles: 2 };
+        assert!(rom.write_tiles(&region, &[1; 16]).is_err());
+        rom.write_tiles(&region, &[1; 32]).unwrap();
+        assert!(rom.is_modified());
+
+        let header = &rom.data()[0x7fc0..];
+        let complement = u16::from_le_bytes([header[0x1c], header[0x1d]]);
+        let checksum = u16::from_le_bytes([header[0x1e], header[0x1f]]);
+        assert_eq!(checksum ^ complement, 0xffff);
+        // the last 32 KiB is mirrored once to fill out 64 KiB: 32 written bytes, plus the
+        // checksum pair's 0x1fe and the title, all in the first 64 KiB
+        let title: u16 = b"TEST GAME            ".iter().map(|b| *b as u16).sum();
+        assert_eq!(checksum, 32 + 0x1fe + title + 0x20 + 0x80);
+        assert_eq!(rom.export(patch::PatchFormat::InPlace).unwrap(), rom.to_bytes());
+    }
+}
@@ -0,0 +1,180 @@
+//! IPS and BPS patches, for shipping ROM edits without shipping the ROM.
+//!
+//! Both are made against the file exactly as it was opened (copier header and all), so they
+//! apply to the same dump the edits were made on.
+
+use crate::Error;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum PatchFormat {
+    /// No patch, overwrite the ROM itself.
+    #[default]
+    InPlace,
+    Ips,
+    Bps,
+}
+
+impl PatchFormat {
+    pub const ALL: [PatchFormat; 3] = [PatchFormat::InPlace, PatchFormat::Ips, PatchFormat::Bps];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PatchFormat::InPlace => "sfc",
+            PatchFormat::Ips => "ips",
+            PatchFormat::Bps => "bps",
+        }
+    }
+
+    pub fn write(&self, source: &[u8], target: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            PatchFormat::InPlace => Ok(target.to_vec()),
+            PatchFormat::Ips => write_ips(source, target),
+            PatchFormat::Bps => Ok(write_bps(source, target)),
+        }
+    }
+}
+
+impl std::fmt::Display for PatchFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            PatchFormat::InPlace => "ROM (in place)",
+            PatchFormat::Ips => "IPS patch",
+            PatchFormat::Bps => "BPS patch",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+/// IPS offsets are 24 bits.
+const IPS_MAX_SIZE: usize = 1 << 24;
+/// A record starting here would read as the "EOF" marker.
+const IPS_EOF_OFFSET: usize = 0x454f46;
+const IPS_MAX_RECORD: usize = 0xffff;
+
+/// Returns: an IPS patch turning `source` into `target`. The target can't be smaller than the
+/// source, since IPS can only truncate with a nonstandard extension.
+pub fn write_ips(source: &[u8], target: &[u8]) -> Result<Vec<u8>, Error> {
+    if target.len() > IPS_MAX_SIZE {
+        return Err(Error::InvalidPatch(format!("IPS can't address past 16 MiB ({} bytes)", target.len())));
+    }
+    if target.len() < source.len() {
+        return Err(Error::InvalidPatch("IPS can't shrink a file".to_owned()));
+    }
+
+    let differs = |i: usize| source.get(i) != Some(&target[i]);
+    let mut patch = b"PATCH".to_vec();
+    let mut i = 0;
+    while i < target.len() {
+        if !differs(i) {
+            i += 1;
+            continue;
+        }
+        // back up a byte rather than write a record that looks like the end marker
+        let start = if i == IPS_EOF_OFFSET { i - 1 } else { i };
+        let mut end = i;
+        while end < target.len() && end - start < IPS_MAX_RECORD && differs(end) {
+            end += 1;
+        }
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&target[start..end]);
+        i = end;
+    }
+    patch.extend_from_slice(b"EOF");
+    Ok(patch)
+}
+
+/// Returns: a BPS patch turning `source` into `target`. Bytes that didn't move are copied from
+/// the source and everything else is stored as is; there's no searching for moved data.
+pub fn write_bps(source: &[u8], target: &[u8]) -> Vec<u8> {
+    const SOURCE_READ: usize = 0;
+    const TARGET_READ: usize = 1;
+
+    let mut patch = b"BPS1".to_vec();
+    write_varint(&mut patch, source.len());
+    write_varint(&mut patch, target.len());
+    // no metadata
+    write_varint(&mut patch, 0);
+
+    let same = |i: usize| source.get(i) == Some(&target[i]);
+    let mut i = 0;
+    while i < target.len() {
+        let kind = same(i);
+        let start = i;
+        while i < target.len() && same(i) == kind {
+            i += 1;
+        }
+        let action = if kind { SOURCE_READ } else { TARGET_READ };
+        write_varint(&mut patch, (i - start - 1) << 2 | action);
+        if !kind {
+            patch.extend_from_slice(&target[start..i]);
+        }
+    }
+
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    let patch_crc = crc32(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+    patch
+}
+
+/// BPS's variable length numbers: 7 bits at a time, low first, with the top bit marking the
+/// last byte. Each continuation subtracts one so there's only one way to encode a number.
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    loop {
+        let x = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(0x80 | x);
+            break;
+        }
+        out.push(x);
+        n -= 1;
+    }
+}
+
+/// Plain CRC-32 (the zlib/PNG one).
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ips() {
+        let source = [0u8; 16];
+        let mut target = source;
+        target[2] = 1;
+        target[3] = 2;
+        target[10] = 3;
+        let patch = write_ips(&source, &target).unwrap();
+        assert_eq!(patch, b"PATCH\x00\x00\x02\x00\x02\x01\x02\x00\x00\x0a\x00\x01\x03EOF");
+    }
+
+    #[test]
+    fn test_bps() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+
+        let mut varint = vec![];
+        write_varint(&mut varint, 128);
+        assert_eq!(varint, [0x00, 0x80]);
+
+        let source = [0u8; 8];
+        let mut target = source;
+        target[4] = 9;
+        let patch = write_bps(&source, &target);
+        // header, sizes, no metadata, then: copy 4 from the source, 1 new byte, copy 3 more
+        assert_eq!(patch[..11], [b'B', b'P', b'S', b'1', 0x88, 0x88, 0x80, 0x8c, 0x81, 9, 0x88]);
+        assert_eq!(patch.len(), 11 + 12);
+        assert_eq!(patch[11..15], crc32(&source).to_le_bytes());
+    }
+}
@@ -1,69 +1,336 @@
+use eframe::egui::Color32;
 use crate::paint;
-use crate::paint::Grid;
+use crate::paint::{CanvasGrid, Grid, VecGrid};
 use crate::paint::Palette;
+use crate::Error;
 
-/// Returns: VRAM data (ret.0) and Palette data (ret.1). Colors stored little-endian (SNES specs)
-pub fn write_out(grid: &dyn Grid<usize>, palette: &Palette) -> (Vec<u8>, Vec<u8>) {
-    let mut v_ram = vec![];
-    let mut pal = vec![];
-    match palette.bpp() {
-        2 => {
-            // iter over index chunks of 8x8, left to right, up to down
-            let num_sprite_width = grid.width() / 8;
-            let num_sprite_height = grid.height() / 8;
-            for i in 0..num_sprite_width {
-                for j in 0..num_sprite_height {
-                    let subgrid = paint::subgrid::<8, 8>(grid, (i*8, (i+1)*8), (j*8, (j+1)*8));
-                    for chunk in 0..subgrid.width() * subgrid.height() / 8 {
-                        // intertwine two bit planes:
-                        let mut bp1 = 0u8;
-                        let mut bp2 = 0u8;
-                        // for each item in the subgrid...
-                        for i in chunk*8..(chunk+1)*8 {
-                            let v = subgrid.idx_linear(i);
-                            // store first bit in bp1
-                            bp1 <<= 1;
-                            // push for next fella
-                            bp1 |= (v as u8 & 0b0001) >> 0;
-                            // store second bit in bp2
-                            bp2 <<= 1;
-                            // push for next fella
-                            bp2 |= (v as u8 & 0b0010) >> 1;
-                        }
-                        // add to array
-                        v_ram.push(bp1);
-                        v_ram.push(bp2);
-                    }
-                    v_ram.extend_from_slice(&[0u8;16]);
+pub mod asm;
+pub mod bitmap;
+pub mod compress;
+pub mod dump;
+pub mod palette;
+pub mod project;
+pub mod source;
+
+/// Packs a color into the SNES's 15-bit BGR format (0bbbbbgg gggrrrrr).
+pub fn to_bgr555(color: Color32) -> u16 {
+    (color.b() as u16 >> 3) << 10 | (color.g() as u16 >> 3) << 5 | (color.r() as u16 >> 3)
+}
+
+/// Inverse of [`to_bgr555`]. The low bits are filled from the high ones so 0x1f maps to 0xff.
+pub fn from_bgr555(bytes: u16) -> Color32 {
+    let expand = |c: u16| -> u8 {
+        let c = (c & 0x1f) as u8;
+        (c << 3) | (c >> 2)
+    };
+    Color32::from_rgb(expand(bytes), expand(bytes >> 5), expand(bytes >> 10))
+}
+
+/// How 8x8 tiles are laid out in VRAM.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum TileOrder {
+    /// Left to right, up to down.
+    #[default]
+    Linear,
+    /// 16x16 sprites the way OBJ tiles expect them: the top half of a sprite at tile `n` is
+    /// `n, n+1` and the bottom half is `n+16, n+17`, so eight sprites share two rows of tiles.
+    Sprite16,
+}
+
+impl TileOrder {
+    pub const ALL: [TileOrder; 2] = [TileOrder::Linear, TileOrder::Sprite16];
+
+    pub fn parse(name: &str) -> Option<TileOrder> {
+        match name.to_ascii_lowercase().as_str() {
+            "linear" | "8x8" => Some(TileOrder::Linear),
+            "sprite16" | "16x16" => Some(TileOrder::Sprite16),
+            _ => None,
+        }
+    }
+
+    /// Width and height in pixels the grid has to be a multiple of.
+    pub fn block_size(&self) -> usize {
+        match self {
+            TileOrder::Linear => 8,
+            TileOrder::Sprite16 => 16,
+        }
+    }
+
+    /// For each VRAM tile slot, which tile (x, y) of the grid goes there. Slots that only exist
+    /// to pad out a row of sprites are `None`.
+    pub fn layout(&self, tiles_wide: usize, tiles_high: usize) -> Vec<Option<(usize, usize)>> {
+        match self {
+            TileOrder::Linear => {
+                (0..tiles_wide * tiles_high).map(|i| Some((i % tiles_wide, i / tiles_wide))).collect()
+            }
+            TileOrder::Sprite16 => {
+                let sprites: Vec<(usize, usize)> = (0..tiles_high / 2)
+                    .flat_map(|y| (0..tiles_wide / 2).map(move |x| (x * 2, y * 2)))
+                    .collect();
+                let mut slots = vec![None; sprites.len().div_ceil(8) * 32];
+                for (n, (x, y)) in sprites.into_iter().enumerate() {
+                    let base = (n / 8) * 32 + (n % 8) * 2;
+                    slots[base] = Some((x, y));
+                    slots[base + 1] = Some((x + 1, y));
+                    slots[base + 16] = Some((x, y + 1));
+                    slots[base + 17] = Some((x + 1, y + 1));
                 }
+                slots
             }
+        }
+    }
+}
 
+impl std::fmt::Display for TileOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            TileOrder::Linear => "8x8 tiles",
+            TileOrder::Sprite16 => "16x16 sprites",
+        };
+        write!(f, "{}", str)
+    }
+}
 
+/// Returns: VRAM data (ret.0) and Palette data (ret.1). Colors stored little-endian (SNES specs)
+pub fn write_out(grid: &dyn Grid<usize>, palette: &Palette) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    write_out_ordered(grid, palette, TileOrder::Linear)
+}
+
+/// Same as [`write_out`], with the tiles laid out in the given order. The grid's size has to be
+/// a multiple of the order's [`TileOrder::block_size`]; anything past the last block is dropped.
+pub fn write_out_ordered(grid: &dyn Grid<usize>, palette: &Palette, order: TileOrder) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let mut v_ram = vec![];
+    let num_sprite_width = grid.width() / 8;
+    let num_sprite_height = grid.height() / 8;
+    for slot in order.layout(num_sprite_width, num_sprite_height) {
+        match slot {
+            Some((i, j)) => {
+                let subgrid = paint::subgrid::<8, 8>(grid, (j*8, (j+1)*8), (i*8, (i+1)*8));
+                v_ram.extend(write_tile(subgrid.as_ref(), palette.bpp())?);
+            }
+            None => v_ram.extend(vec![0u8; tile_size(palette.bpp())]),
         }
-        3 => {
-            unimplemented!()
-        }
-        4 => {
-            unimplemented!()
+    }
+
+    Ok((v_ram, write_palette(palette)))
+}
+
+/// Returns: which VRAM tile slot pixel (x, y) of a `width`x`height` grid is written to under
+/// `order`, and the byte offset of that tile, or `None` if the order leaves it out.
+pub fn vram_position(x: usize, y: usize, width: usize, height: usize, bpp: usize, order: TileOrder) -> Option<(usize, usize)> {
+    let slot = order.layout(width / 8, height / 8).iter().position(|slot| *slot == Some((x / 8, y / 8)))?;
+    Some((slot, slot * tile_size(bpp)))
+}
+
+/// Returns: a BG tilemap for the grid, one little-endian entry per 8x8 tile, left to right and up
+/// to down. Entries are `vhopppcc cccccccc`: tile number (where `order` put the tile in VRAM),
+/// palette slot, priority and flips. There's no flipping or deduplication.
+pub fn write_tilemap(grid: &dyn Grid<usize>, palette_slot: u8, order: TileOrder) -> Vec<u8> {
+    let num_sprite_width = grid.width() / 8;
+    let num_sprite_height = grid.height() / 8;
+    let layout = order.layout(num_sprite_width, num_sprite_height);
+    let mut map = vec![];
+    for j in 0..num_sprite_height {
+        for i in 0..num_sprite_width {
+            // tiles past the last full block aren't in VRAM at all; point them at tile 0
+            let tile = layout.iter().position(|slot| *slot == Some((i, j))).unwrap_or(0);
+            let entry = (tile as u16 & 0x03ff) | ((palette_slot as u16 & 0b111) << 10);
+            map.extend_from_slice(&entry.to_le_bytes());
         }
-        8 => {
-            unimplemented!()
+    }
+    map
+}
+
+/// Number of bytes a single 8x8 tile takes up in VRAM.
+pub const fn tile_size(bpp: usize) -> usize {
+    bpp * 8
+}
+
+/// Encodes one 8x8 tile in the SNES's planar format. Bit planes come in pairs: each row stores
+/// a byte of the low plane followed by a byte of the high one, and each pair of planes follows
+/// the last (so 4bpp is planes 0/1 for all rows, then planes 2/3). A leftover odd plane (3bpp)
+/// is stored by itself, one byte per row.
+pub fn write_tile(tile: &dyn Grid<usize>, bpp: usize) -> Result<Vec<u8>, Error> {
+    if !matches!(bpp, 1..=4 | 8) {
+        return Err(Error::UnsupportedBpp(bpp));
+    }
+
+    let plane_row = |plane: usize, row: usize| -> u8 {
+        let mut bp = 0u8;
+        for col in 0..8 {
+            // push for next fella
+            bp <<= 1;
+            bp |= ((tile.get(col, row) >> plane) & 1) as u8;
         }
-        _ => {
-            panic!("Bad bpp mode {}!!", palette.bpp());
+        bp
+    };
+
+    let mut out = Vec::with_capacity(tile_size(bpp));
+    for plane in (0..bpp).step_by(2) {
+        for row in 0..8 {
+            // intertwine two bit planes
+            out.push(plane_row(plane, row));
+            if plane + 1 < bpp {
+                out.push(plane_row(plane + 1, row));
+            }
         }
     }
+    Ok(out)
+}
 
+/// Returns: Palette data as CGRAM expects it, one little-endian BGR555 word per color.
+pub fn write_palette(palette: &Palette) -> Vec<u8> {
+    let mut pal = vec![];
     for c in 0..palette.size() {
-        let color = palette[c];
-        let bytes: u16 = {
-            (color.b() as u16 >> 3) << 10 | (color.g() as u16 >> 3) << 5 | (color.r() as u16 >> 3)
-        };
+        let bytes = to_bgr555(palette[c]);
         let ls_byte = (bytes & 0x00ff) as u8;
         let ms_byte = ((bytes & 0xff00) >> 8) as u8;
         pal.push(ls_byte);
         pal.push(ms_byte);
     }
+    pal
+}
+
+/// Lays bytes out as lines of generated source (the assembler, C and Rust exports), `per_line`
+/// to a line. `line` gets each line's number and its bytes, already rendered by `byte`, and
+/// returns the text of the line.
+pub fn write_byte_lines(
+    out: &mut String,
+    bytes: &[u8],
+    per_line: usize,
+    byte: impl Fn(u8) -> String,
+    line: impl Fn(usize, &[String]) -> String,
+) {
+    for (i, chunk) in bytes.chunks(per_line).enumerate() {
+        let chunk: Vec<String> = chunk.iter().map(|b| byte(*b)).collect();
+        out.push_str(&line(i, &chunk));
+        out.push('\n');
+    }
+}
 
-    (v_ram, pal)
-}
\ No newline at end of file
+/// Decodes one 8x8 tile written by [`write_tile`]. `bytes` has to be at least
+/// [`tile_size`] long.
+pub fn read_tile(bytes: &[u8], bpp: usize) -> CanvasGrid<8, 8> {
+    let mut tile = CanvasGrid::<8, 8>::new();
+    let mut pos = 0;
+    for plane in (0..bpp).step_by(2) {
+        let planes = if plane + 1 < bpp { 2 } else { 1 };
+        for row in 0..8 {
+            for p in 0..planes {
+                let bp = bytes[pos];
+                pos += 1;
+                for col in 0..8 {
+                    let bit = ((bp >> (7 - col)) & 1) as usize;
+                    tile.set(col, row, tile.get(col, row) | bit << (plane + p));
+                }
+            }
+        }
+    }
+    tile
+}
+
+/// Decodes as many whole tiles as `bytes` holds into a grid `tiles_wide` tiles across, left to
+/// right and up to down. The last row is padded out with color 0.
+pub fn read_tiles(bytes: &[u8], bpp: usize, tiles_wide: usize) -> VecGrid {
+    let num_tiles = bytes.len() / tile_size(bpp);
+    let tiles_high = num_tiles.div_ceil(tiles_wide).max(1);
+    let mut grid = VecGrid::new(tiles_wide * 8, tiles_high * 8);
+    for (n, chunk) in bytes.chunks_exact(tile_size(bpp)).enumerate() {
+        let tile = read_tile(chunk, bpp);
+        let (tx, ty) = (n % tiles_wide, n / tiles_wide);
+        for x in 0..8 {
+            for y in 0..8 {
+                grid.set(tx * 8 + x, ty * 8 + y, tile.get(x, y));
+            }
+        }
+    }
+    grid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagonal_tile() -> CanvasGrid<8, 8> {
+        let mut tile = CanvasGrid::<8, 8>::new();
+        for i in 0..8 {
+            tile.set(i, i, i + 1);
+        }
+        tile
+    }
+
+    #[test]
+    fn test_write_tile_2bpp() {
+        let tile = diagonal_tile();
+        let bytes = write_tile(&tile, 2).unwrap();
+        assert_eq!(bytes.len(), tile_size(2));
+        // row 0 has color 1 in column 0, row 1 has color 2 in column 1, row 2 has color 3...
+        assert_eq!(&bytes[0..6], &[0x80, 0x00, 0x00, 0x40, 0x20, 0x20]);
+        assert!(matches!(write_tile(&tile, 5), Err(Error::UnsupportedBpp(5))));
+    }
+
+    #[test]
+    fn test_write_out_order_and_size() {
+        // a 16x8 grid: the left tile is color 1, the right one color 2
+        let mut grid = VecGrid::new(16, 8);
+        for y in 0..8 {
+            for x in 8..16 {
+                grid.set(x, y, 2);
+            }
+            grid.set(0, y, 1);
+        }
+        let mut palette = Palette::new();
+        palette.set_bpp(2).unwrap();
+        let (v_ram, pal) = write_out(&grid, &palette).unwrap();
+        // tiles go left to right with nothing between them
+        assert_eq!(v_ram.len(), 2 * tile_size(2));
+        assert_eq!(&v_ram[0..2], &[0x80, 0x00]);
+        assert_eq!(&v_ram[16..18], &[0x00, 0xff]);
+        assert_eq!(pal.len(), 8);
+    }
+
+    #[test]
+    fn test_read_tile_round_trip() {
+        let tile = diagonal_tile();
+        for bpp in [1, 2, 3, 4, 8] {
+            let read = read_tile(&write_tile(&tile, bpp).unwrap(), bpp);
+            for i in 0..8 {
+                assert_eq!(read.get(i, i), (i + 1) & ((1 << bpp) - 1), "{bpp}bpp");
+            }
+        }
+    }
+
+    #[test]
+    fn test_sprite16_layout() {
+        // two 16x16 sprites side by side
+        let layout = TileOrder::Sprite16.layout(4, 2);
+        assert_eq!(layout.len(), 32);
+        assert_eq!(layout[0..4], [Some((0, 0)), Some((1, 0)), Some((2, 0)), Some((3, 0))]);
+        assert_eq!(layout[16..20], [Some((0, 1)), Some((1, 1)), Some((2, 1)), Some((3, 1))]);
+        assert_eq!(layout[4], None);
+    }
+
+    #[test]
+    fn test_vram_position() {
+        assert_eq!(vram_position(9, 3, 32, 16, 4, TileOrder::Linear), Some((1, 32)));
+        assert_eq!(vram_position(3, 9, 32, 16, 4, TileOrder::Linear), Some((4, 128)));
+        // the bottom left of the first sprite is tile 16
+        assert_eq!(vram_position(3, 9, 32, 16, 2, TileOrder::Sprite16), Some((16, 256)));
+        // a row of tiles that doesn't make a whole sprite isn't written at all
+        assert_eq!(vram_position(0, 16, 32, 24, 2, TileOrder::Sprite16), None);
+    }
+
+    #[test]
+    fn test_write_tile_3bpp_and_4bpp() {
+        let tile = diagonal_tile();
+        let three = write_tile(&tile, 3).unwrap();
+        let four = write_tile(&tile, 4).unwrap();
+        assert_eq!(three.len(), 24);
+        assert_eq!(four.len(), 32);
+        // planes 0/1 are the same no matter the depth
+        assert_eq!(three[..16], four[..16]);
+        // row 3 has color 4, so plane 2 is set in column 3
+        assert_eq!(three[16 + 3], 0x10);
+        assert_eq!(four[16 + 3 * 2], 0x10);
+    }
+}
@@ -0,0 +1,133 @@
+//! Assembler source output, for builds that include graphics as source instead of `.incbin`.
+
+use std::fmt::Write;
+use crate::serde::{tile_size, write_byte_lines};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Assembler {
+    #[default]
+    Ca65,
+    Asar,
+    WlaDx,
+}
+
+impl Assembler {
+    pub const ALL: [Assembler; 3] = [Assembler::Ca65, Assembler::Asar, Assembler::WlaDx];
+
+    /// The data directive for a list of bytes.
+    fn byte_directive(&self) -> &'static str {
+        match self {
+            Assembler::Ca65 => ".byte",
+            Assembler::Asar => "db",
+            Assembler::WlaDx => ".db",
+        }
+    }
+
+    fn constant(&self, name: &str, value: usize) -> String {
+        match self {
+            Assembler::Ca65 => format!("{name} = {value}"),
+            Assembler::Asar => format!("!{name} = {value}"),
+            Assembler::WlaDx => format!(".DEFINE {name} {value}"),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Assembler::Ca65 => "s",
+            Assembler::Asar | Assembler::WlaDx => "asm",
+        }
+    }
+}
+
+impl std::fmt::Display for Assembler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Assembler::Ca65 => "ca65",
+            Assembler::Asar => "asar",
+            Assembler::WlaDx => "WLA-DX",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+/// Turns anything (usually a file name) into something every assembler accepts as a label.
+pub fn label(name: &str) -> String {
+    let mut label: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if label.is_empty() || label.starts_with(|c: char| c.is_ascii_digit()) {
+        label.insert(0, '_');
+    }
+    label
+}
+
+/// Renders the output of [`crate::serde::write_out`] as assembler source. Tiles and palette each
+/// get a label (`name_tiles`, `name_palette`) and a constant holding their size in bytes.
+pub fn write_asm(name: &str, v_ram: &[u8], pal: &[u8], bpp: usize, asm: Assembler) -> String {
+    let name = label(name);
+    let tile_bytes = tile_size(bpp);
+    let mut out = String::new();
+
+    writeln!(out, "; {} tiles at {}bpp, {} colors", v_ram.len() / tile_bytes, bpp, pal.len() / 2).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "{}", asm.constant(&format!("{name}_tiles_size"), v_ram.len())).unwrap();
+    writeln!(out, "{}", asm.constant(&format!("{name}_palette_size"), pal.len())).unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "{name}_tiles:").unwrap();
+    for (i, tile) in v_ram.chunks(tile_bytes).enumerate() {
+        writeln!(out, "; tile {i}").unwrap();
+        write_bytes(&mut out, tile, asm);
+    }
+    writeln!(out).unwrap();
+
+    writeln!(out, "{name}_palette:").unwrap();
+    write_bytes(&mut out, pal, asm);
+
+    out
+}
+
+/// Writes bytes 16 to a line, which keeps 2bpp tiles to one line per tile.
+fn write_bytes(out: &mut String, bytes: &[u8], asm: Assembler) {
+    write_byte_lines(out, bytes, 16, |b| format!("${b:02x}"), |_, line| {
+        format!("{} {}", asm.byte_directive(), line.join(","))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_asm_dialects() {
+        // two 2bpp tiles and a 4 color palette
+        let v_ram: Vec<u8> = (0..32).collect();
+        let pal = [0x00, 0x00, 0xff, 0x7f, 0x1f, 0x00, 0xe0, 0x03];
+
+        let ca65 = write_asm("hero 1", &v_ram, &pal, 2, Assembler::Ca65);
+        let lines: Vec<&str> = ca65.lines().collect();
+        assert_eq!(lines[0], "; 2 tiles at 2bpp, 4 colors");
+        assert_eq!(lines[2], "hero_1_tiles_size = 32");
+        assert_eq!(lines[3], "hero_1_palette_size = 8");
+        assert_eq!(lines[5], "hero_1_tiles:");
+        assert_eq!(lines[6], "; tile 0");
+        assert_eq!(lines[7], ".byte $00,$01,$02,$03,$04,$05,$06,$07,$08,$09,$0a,$0b,$0c,$0d,$0e,$0f");
+        assert_eq!(lines[8], "; tile 1");
+        assert_eq!(lines[11], "hero_1_palette:");
+        assert_eq!(lines[12], ".byte $00,$00,$ff,$7f,$1f,$00,$e0,$03");
+
+        let asar = write_asm("hero", &v_ram, &pal, 2, Assembler::Asar);
+        assert!(asar.contains("\n!hero_tiles_size = 32\n"));
+        assert!(asar.contains("\ndb $00,$00,$ff,$7f"));
+        let wla = write_asm("hero", &v_ram, &pal, 2, Assembler::WlaDx);
+        assert!(wla.contains("\n.DEFINE hero_palette_size 8\n"));
+        assert!(wla.contains("\n.db $00,$00,$ff,$7f"));
+    }
+
+    #[test]
+    fn test_label() {
+        assert_eq!(label("my-sprite.png"), "my_sprite_png");
+        assert_eq!(label("8x8"), "_8x8");
+        assert_eq!(label(""), "_");
+    }
+}
@@ -0,0 +1,70 @@
+//! PNG import: turns a truecolor image into palette indices.
+
+use std::path::Path;
+use eframe::egui::Color32;
+use crate::paint::{Grid, Palette, VecGrid};
+use crate::serde::to_bgr555;
+use crate::Error;
+
+/// Reads a PNG into a grid of palette indices. Colors are compared as the SNES sees them
+/// (BGR555), so shades that only differ in their low bits share an index.
+///
+/// With a `palette`, every opaque pixel has to match one of its colors. Without one, a palette
+/// is built from the colors in the order they appear. Fully transparent pixels always become
+/// index 0, the SNES's transparent color.
+pub fn read_png(path: &Path, palette: Option<&Palette>) -> Result<(VecGrid, Palette), Error> {
+    let image = image::open(path)
+        .map_err(|e| Error::InvalidImage(format!("{}: {e}", path.display())))?
+        .to_rgba8();
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    if width % 8 != 0 || height % 8 != 0 || width == 0 || height == 0 {
+        return Err(Error::InvalidImage(format!("{}: {width}x{height} isn't a whole number of 8x8 tiles", path.display())));
+    }
+
+    let has_transparency = image.pixels().any(|p| p.0[3] == 0);
+    let mut colors: Vec<Color32> = match palette {
+        Some(palette) => palette.colors().to_vec(),
+        None if has_transparency => vec![Color32::BLACK],
+        None => vec![],
+    };
+    let mut grid = VecGrid::new(width, height);
+
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let [r, g, b, a] = pixel.0;
+        if a == 0 {
+            grid.set(x as usize, y as usize, 0);
+            continue;
+        }
+        let color = Color32::from_rgb(r, g, b);
+        // index 0 is reserved for transparent pixels if there are any
+        let skip = if palette.is_none() && has_transparency { 1 } else { 0 };
+        let idx = colors.iter()
+            .skip(skip)
+            .position(|c| to_bgr555(*c) == to_bgr555(color))
+            .map(|i| i + skip);
+        let idx = match (idx, palette) {
+            (Some(idx), _) => idx,
+            (None, None) => {
+                colors.push(color);
+                colors.len() - 1
+            }
+            (None, Some(_)) => {
+                return Err(Error::InvalidImage(format!(
+                    "{}: color #{r:02x}{g:02x}{b:02x} at ({x}, {y}) isn't in the palette",
+                    path.display(),
+                )));
+            }
+        };
+        grid.set(x as usize, y as usize, idx);
+    }
+
+    if colors.len() > 256 {
+        return Err(Error::InvalidImage(format!("{}: {} colors won't fit in a 256 color palette", path.display(), colors.len())));
+    }
+
+    let palette = match palette {
+        Some(palette) => palette.clone(),
+        None => Palette::from_colors(&colors),
+    };
+    Ok((grid, palette))
+}
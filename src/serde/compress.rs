@@ -0,0 +1,446 @@
+//! Compression formats games use for graphics, named the way Lunar Compress names them.
+//!
+//! LC_LZ2 and LC_LZ3 share a command header: `CCCLLLLL`, a 3 bit command and a 5 bit length
+//! minus one. Command 7 means the length didn't fit, and the real header is two bytes:
+//! `111CCCLL LLLLLLLL`, with a 10 bit length minus one. 0xFF ends the stream.
+//!
+//! | cmd | LC_LZ2                        | LC_LZ3                                 |
+//! |-----|-------------------------------|----------------------------------------|
+//! | 0   | copy the next L bytes         | copy the next L bytes                  |
+//! | 1   | fill with one byte            | fill with one byte                     |
+//! | 2   | fill with two alternating bytes | fill with two alternating bytes      |
+//! | 3   | fill with a byte, +1 each time| fill with zeroes (no operand)          |
+//! | 4   | repeat output from a 16 bit big-endian address | repeat output from an address* |
+//! | 5   |                               | repeat with each byte's bits reversed* |
+//! | 6   |                               | repeat going backwards*                |
+//!
+//! *LZ3 addresses are one byte `1ddddddd` meaning `d + 1` bytes back, or two bytes big-endian
+//! `0aaaaaaa aaaaaaaa` for an absolute address.
+//!
+//! RLE is a simpler byte-oriented format of our own: `0nnnnnnn` copies the next `n + 1` bytes,
+//! `1nnnnnnn` repeats the next byte `n + 2` times, and 0xFF ends the stream.
+
+use std::collections::HashMap;
+use crate::Error;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Compression {
+    #[default]
+    None,
+    Rle,
+    Lz2,
+    Lz3,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 4] = [Compression::None, Compression::Rle, Compression::Lz2, Compression::Lz3];
+
+    pub fn parse(name: &str) -> Option<Compression> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(Compression::None),
+            "rle" => Some(Compression::Rle),
+            "lz2" | "lc_lz2" => Some(Compression::Lz2),
+            "lz3" | "lc_lz3" => Some(Compression::Lz3),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Compression::None => "None",
+            Compression::Rle => "RLE",
+            Compression::Lz2 => "LC_LZ2",
+            Compression::Lz3 => "LC_LZ3",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+const END: u8 = 0xff;
+const MAX_LENGTH: usize = 1024;
+
+pub fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+    match compression {
+        Compression::None => data.to_vec(),
+        Compression::Rle => compress_rle(data),
+        Compression::Lz2 | Compression::Lz3 => compress_lz(data, compression == Compression::Lz3),
+    }
+}
+
+pub fn decompress(data: &[u8], compression: Compression) -> Result<Vec<u8>, Error> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Rle => decompress_rle(data),
+        Compression::Lz2 | Compression::Lz3 => decompress_lz(data, compression == Compression::Lz3),
+    }
+}
+
+/// Compresses `data`, then makes sure it decompresses back to exactly `data` before handing it
+/// over. A broken encoder would otherwise only show up as garbage on real hardware.
+pub fn compress_checked(data: &[u8], compression: Compression) -> Result<Vec<u8>, Error> {
+    let compressed = compress(data, compression);
+    if decompress(&compressed, compression)? != data {
+        return Err(Error::InvalidCompressedData(format!("{compression} round trip didn't match the input")));
+    }
+    Ok(compressed)
+}
+
+/// Compressed size as a percentage of the original.
+pub fn ratio(original: usize, compressed: usize) -> f32 {
+    if original == 0 {
+        return 100.0;
+    }
+    compressed as f32 / original as f32 * 100.0
+}
+
+fn compress_rle(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut literal: Vec<u8> = vec![];
+    let flush = |out: &mut Vec<u8>, literal: &mut Vec<u8>| {
+        for chunk in literal.chunks(128) {
+            out.push(chunk.len() as u8 - 1);
+            out.extend_from_slice(chunk);
+        }
+        literal.clear();
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..].iter().take(128).take_while(|b| **b == data[i]).count();
+        if run >= 3 {
+            flush(&mut out, &mut literal);
+            out.push(0x80 | (run - 2) as u8);
+            out.push(data[i]);
+            i += run;
+        } else {
+            literal.push(data[i]);
+            i += 1;
+        }
+    }
+    flush(&mut out, &mut literal);
+    out.push(END);
+    out
+}
+
+fn decompress_rle(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut out = vec![];
+    let mut input = Reader { data, pos: 0 };
+    loop {
+        let control = input.byte()?;
+        match control {
+            END => return Ok(out),
+            0x80.. => {
+                let byte = input.byte()?;
+                out.extend(std::iter::repeat_n(byte, (control & 0x7f) as usize + 2));
+            }
+            _ => out.extend_from_slice(input.bytes(control as usize + 1)?),
+        }
+    }
+}
+
+/// One step of an LZ stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Command {
+    Copy,
+    ByteFill(u8),
+    WordFill(u8, u8),
+    IncreasingFill(u8),
+    ZeroFill,
+    Repeat(usize),
+    ReversedRepeat(usize),
+    BackwardsRepeat(usize),
+}
+
+impl Command {
+    fn id(&self) -> u8 {
+        match self {
+            Command::Copy => 0,
+            Command::ByteFill(_) => 1,
+            Command::WordFill(..) => 2,
+            Command::IncreasingFill(_) | Command::ZeroFill => 3,
+            Command::Repeat(_) => 4,
+            Command::ReversedRepeat(_) => 5,
+            Command::BackwardsRepeat(_) => 6,
+        }
+    }
+
+    /// Bytes the command takes after its header, for an address relative to `pos` in LZ3.
+    fn operand_size(&self, pos: usize, lz3: bool) -> usize {
+        match self {
+            Command::Copy | Command::ZeroFill => 0,
+            Command::ByteFill(_) | Command::IncreasingFill(_) => 1,
+            Command::WordFill(..) => 2,
+            Command::Repeat(src) | Command::ReversedRepeat(src) | Command::BackwardsRepeat(src) => {
+                if lz3 && pos - src <= 0x80 { 1 } else { 2 }
+            }
+        }
+    }
+
+    /// Whether a repeat at `pos` can point at `src` at all. Absolute addresses are 16 bits, and
+    /// in LZ3 their top bit is taken by the relative flag.
+    fn can_address(src: usize, pos: usize, lz3: bool) -> bool {
+        if lz3 {
+            pos - src <= 0x80 || src < 0x8000
+        } else {
+            src <= 0xffff
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>, len: usize, pos: usize, lz3: bool) {
+        let (id, len) = (self.id(), len - 1);
+        if len < 32 {
+            out.push(id << 5 | len as u8);
+        } else {
+            out.push(0xe0 | id << 2 | (len >> 8) as u8);
+            out.push(len as u8);
+        }
+        match *self {
+            Command::Copy | Command::ZeroFill => {}
+            Command::ByteFill(b) | Command::IncreasingFill(b) => out.push(b),
+            Command::WordFill(a, b) => out.extend_from_slice(&[a, b]),
+            Command::Repeat(src) | Command::ReversedRepeat(src) | Command::BackwardsRepeat(src) => {
+                if self.operand_size(pos, lz3) == 1 {
+                    out.push(0x80 | (pos - src - 1) as u8);
+                } else {
+                    out.extend_from_slice(&(src as u16).to_be_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Finds the longest run of a single command starting at `pos`. Returns the command and how
+/// many bytes it covers.
+fn best_command(data: &[u8], pos: usize, lz3: bool, index: &HashMap<[u8; 2], Vec<usize>>) -> Option<(Command, usize)> {
+    let rest = &data[pos..];
+    let limit = Ord::min(rest.len(), MAX_LENGTH);
+    let count = |f: &dyn Fn(usize) -> bool| (0..limit).take_while(|i| f(*i)).count();
+    let mut candidates = vec![];
+
+    let first = rest[0];
+    let byte_run = count(&|i| rest[i] == first);
+    if lz3 && first == 0 {
+        candidates.push((Command::ZeroFill, byte_run));
+    } else {
+        candidates.push((Command::ByteFill(first), byte_run));
+    }
+    if rest.len() >= 2 {
+        let second = rest[1];
+        candidates.push((Command::WordFill(first, second), count(&|i| rest[i] == if i % 2 == 0 { first } else { second })));
+    }
+    if !lz3 {
+        candidates.push((Command::IncreasingFill(first), count(&|i| rest[i] == first.wrapping_add(i as u8))));
+    }
+
+    if rest.len() >= 2 {
+        for &src in index.get(&[rest[0], rest[1]]).into_iter().flatten().rev().take(256) {
+            if src >= pos || !Command::can_address(src, pos, lz3) {
+                continue;
+            }
+            let len = count(&|i| data[src + i] == rest[i]);
+            candidates.push((Command::Repeat(src), len));
+        }
+    }
+    if lz3 {
+        for src in pos.saturating_sub(0x800)..pos {
+            if !Command::can_address(src, pos, lz3) {
+                continue;
+            }
+            if data[src].reverse_bits() == first {
+                let len = count(&|i| src + i < pos && data[src + i].reverse_bits() == rest[i]);
+                candidates.push((Command::ReversedRepeat(src), len));
+            }
+            if data[src] == first {
+                let len = count(&|i| i <= src && data[src - i] == rest[i]);
+                candidates.push((Command::BackwardsRepeat(src), len));
+            }
+        }
+    }
+
+    // a command is only worth it if it saves more than the header it costs to break up a copy
+    candidates.into_iter()
+        .filter(|(cmd, len)| *len > cmd.operand_size(pos, lz3) + 1)
+        .max_by_key(|(cmd, len)| (*len as isize - cmd.operand_size(pos, lz3) as isize, -(cmd.id() as isize)))
+}
+
+fn compress_lz(data: &[u8], lz3: bool) -> Vec<u8> {
+    let mut out = vec![];
+    let mut index: HashMap<[u8; 2], Vec<usize>> = HashMap::new();
+    let mut literal_start = 0;
+    let mut pos = 0;
+
+    let flush = |out: &mut Vec<u8>, from: usize, to: usize| {
+        let mut start = from;
+        while start < to {
+            let len = Ord::min(to - start, MAX_LENGTH);
+            Command::Copy.write(out, len, start, lz3);
+            out.extend_from_slice(&data[start..start + len]);
+            start += len;
+        }
+    };
+
+    while pos < data.len() {
+        match best_command(data, pos, lz3, &index) {
+            Some((cmd, len)) => {
+                flush(&mut out, literal_start, pos);
+                cmd.write(&mut out, len, pos, lz3);
+                for i in pos..pos + len {
+                    if i + 1 < data.len() {
+                        index.entry([data[i], data[i + 1]]).or_default().push(i);
+                    }
+                }
+                pos += len;
+                literal_start = pos;
+            }
+            None => {
+                if pos + 1 < data.len() {
+                    index.entry([data[pos], data[pos + 1]]).or_default().push(pos);
+                }
+                pos += 1;
+            }
+        }
+    }
+    flush(&mut out, literal_start, pos);
+    out.push(END);
+    out
+}
+
+fn decompress_lz(data: &[u8], lz3: bool) -> Result<Vec<u8>, Error> {
+    let mut out: Vec<u8> = vec![];
+    let mut input = Reader { data, pos: 0 };
+    loop {
+        let header = input.byte()?;
+        if header == END {
+            return Ok(out);
+        }
+        let (cmd, len) = if header >> 5 == 7 {
+            let low = input.byte()?;
+            ((header >> 2) & 0b111, (((header & 0b11) as usize) << 8 | low as usize) + 1)
+        } else {
+            (header >> 5, (header & 0x1f) as usize + 1)
+        };
+
+        match (cmd, lz3) {
+            (0, _) => out.extend_from_slice(input.bytes(len)?),
+            (1, _) => {
+                let b = input.byte()?;
+                out.extend(std::iter::repeat_n(b, len));
+            }
+            (2, _) => {
+                let (a, b) = (input.byte()?, input.byte()?);
+                out.extend((0..len).map(|i| if i % 2 == 0 { a } else { b }));
+            }
+            (3, false) => {
+                let b = input.byte()?;
+                out.extend((0..len).map(|i| b.wrapping_add(i as u8)));
+            }
+            (3, true) => out.extend(std::iter::repeat_n(0, len)),
+            (4..=6, _) if lz3 || cmd == 4 => {
+                let first = input.byte()?;
+                let src = if lz3 && first & 0x80 != 0 {
+                    out.len().checked_sub((first & 0x7f) as usize + 1)
+                        .ok_or_else(|| invalid("relative address before the start of the output"))?
+                } else {
+                    u16::from_be_bytes([first, input.byte()?]) as usize
+                };
+                for i in 0..len {
+                    let b = match cmd {
+                        4 => out.get(src + i).copied(),
+                        5 => out.get(src + i).map(|b| b.reverse_bits()),
+                        _ => src.checked_sub(i).and_then(|s| out.get(s)).copied(),
+                    };
+                    out.push(b.ok_or_else(|| invalid("repeat reads past the output"))?);
+                }
+            }
+            _ => return Err(invalid(&format!("unknown command {cmd}"))),
+        }
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::InvalidCompressedData(msg.to_owned())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or_else(|| invalid("unexpected end of data"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Vec<u8>> {
+        // a little xorshift so the "random" sample is the same every run
+        let mut x = 0x2545_f491u32;
+        let noise: Vec<u8> = (0..3000).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        }).collect();
+        let tiles: Vec<u8> = (0..2048).map(|i| if i % 32 < 16 { (i / 7) as u8 } else { 0 }).collect();
+        let mirrored: Vec<u8> = (0..64u8).chain((0..64u8).rev()).chain((0..64u8).map(u8::reverse_bits)).collect();
+        vec![vec![], vec![7], vec![0; 5000], noise, tiles, mirrored, (0..=255).collect()]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for compression in Compression::ALL {
+            for sample in samples() {
+                let compressed = compress_checked(&sample, compression).unwrap();
+                assert_eq!(decompress(&compressed, compression).unwrap(), sample, "{compression}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_lz2_decodes_known_stream() {
+        // byte fill x4, copy 2, increasing fill x3, repeat 4 from address 0 (long header)
+        let stream = [0x23, 0xaa, 0x01, 0x01, 0x02, 0x62, 0x10, 0xf0, 0x03, 0x00, 0x00, 0xff];
+        assert_eq!(
+            decompress(&stream, Compression::Lz2).unwrap(),
+            [0xaa, 0xaa, 0xaa, 0xaa, 0x01, 0x02, 0x10, 0x11, 0x12, 0xaa, 0xaa, 0xaa, 0xaa],
+        );
+    }
+
+    #[test]
+    fn test_lz3_round_trip_past_32k() {
+        // the same tile over and over, so repeats want sources above 0x8000
+        let mut x = 0x1234_5678u32;
+        let tile: Vec<u8> = (0..1024).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        }).collect();
+        let mut data = tile.repeat(40);
+        data.extend((0..2048).map(|i| tile[(i * 7) % 1024].reverse_bits()));
+        for compression in [Compression::Lz2, Compression::Lz3] {
+            let compressed = compress_checked(&data, compression).unwrap();
+            assert!(compressed.len() < data.len() / 4, "{compression}");
+        }
+    }
+
+    #[test]
+    fn test_compression_helps() {
+        let zeroes = vec![0; 4096];
+        for compression in [Compression::Rle, Compression::Lz2, Compression::Lz3] {
+            assert!(compress(&zeroes, compression).len() < zeroes.len() / 32, "{compression}");
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! VRAM and CGRAM dumps as saved by emulator debuggers (Mesen-S, bsnes-plus): the raw 64 KiB of
+//! VRAM and 512 bytes of CGRAM, no headers.
+
+use std::fs;
+use std::path::Path;
+use eframe::egui::Color32;
+use crate::paint::Palette;
+use crate::serde::palette::{self, PaletteFormat};
+use crate::serde::tile_size;
+use crate::Error;
+
+pub const VRAM_SIZE: usize = 0x10000;
+pub const CGRAM_SIZE: usize = 512;
+
+pub fn load_vram(path: &Path) -> Result<Vec<u8>, Error> {
+    let data = fs::read(path)?;
+    if data.len() != VRAM_SIZE {
+        return Err(Error::InvalidDump(format!("VRAM dumps are {VRAM_SIZE} bytes, got {}", data.len())));
+    }
+    Ok(data)
+}
+
+pub fn load_cgram(path: &Path) -> Result<Vec<Color32>, Error> {
+    let data = fs::read(path)?;
+    if data.len() != CGRAM_SIZE {
+        return Err(Error::InvalidDump(format!("CGRAM dumps are {CGRAM_SIZE} bytes, got {}", data.len())));
+    }
+    palette::read_palette(&data, PaletteFormat::Bgr555)
+}
+
+/// Returns: `count` tiles' worth of VRAM starting at a word address. VRAM is addressed in
+/// 16 bit words, and reads past the end wrap back to the start like the PPU does.
+pub fn vram_tiles(vram: &[u8], word_address: usize, bpp: usize, count: usize) -> Vec<u8> {
+    let start = word_address * 2;
+    (0..count * tile_size(bpp)).map(|i| vram[(start + i) % vram.len()]).collect()
+}
+
+/// Number of sub-palettes of a given depth that fit in CGRAM.
+pub fn sub_palettes(bpp: usize) -> usize {
+    256 >> bpp
+}
+
+/// Returns: sub-palette `index` of CGRAM, as many colors as `bpp` allows. OBJ palettes are
+/// sub-palettes 8-15 at 4bpp.
+pub fn sub_palette(cgram: &[Color32], bpp: usize, index: usize) -> Result<Palette, Error> {
+    let size = 1 << bpp;
+    let start = (index * size) % cgram.len();
+    let mut palette = Palette::new();
+    palette.set_bpp(bpp)?;
+    palette.fit_colors(&cgram[start..Ord::min(start + size, cgram.len())]);
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vram_wraps_and_sub_palettes() {
+        let vram: Vec<u8> = (0..VRAM_SIZE).map(|i| i as u8).collect();
+        let tiles = vram_tiles(&vram, 0x7ffc, 2, 1);
+        assert_eq!(tiles[..8], [0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe, 0xff]);
+        assert_eq!(tiles[8..10], [0x00, 0x01]);
+
+        let cgram: Vec<Color32> = (0..256).map(|i| Color32::from_gray(i as u8)).collect();
+        assert_eq!(sub_palettes(4), 16);
+        let obj = sub_palette(&cgram, 4, 8).unwrap();
+        assert_eq!(obj.size(), 16);
+        assert_eq!(obj[0], Color32::from_gray(128));
+        assert_eq!(sub_palette(&cgram, 2, 1).unwrap()[3], Color32::from_gray(7));
+    }
+}
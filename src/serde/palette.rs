@@ -0,0 +1,240 @@
+//! Palette files other tools understand: GIMP (.gpl), JASC-PAL (also what Aseprite writes as
+//! .pal), Adobe Color Table (.act), plain hex lists (.hex) and raw CGRAM dumps in BGR555.
+
+use std::fs;
+use std::path::Path;
+use eframe::egui::Color32;
+use crate::paint::Palette;
+use crate::serde::{from_bgr555, write_palette};
+use crate::Error;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum PaletteFormat {
+    #[default]
+    Gpl,
+    Jasc,
+    Act,
+    Hex,
+    Bgr555,
+}
+
+impl PaletteFormat {
+    pub const ALL: [PaletteFormat; 5] = [
+        PaletteFormat::Gpl,
+        PaletteFormat::Jasc,
+        PaletteFormat::Act,
+        PaletteFormat::Hex,
+        PaletteFormat::Bgr555,
+    ];
+
+    /// Guesses a format from a file extension. `.pal` is ambiguous, so it's checked for a
+    /// JASC header first and treated as raw BGR555 otherwise.
+    pub fn detect(path: &Path, data: &[u8]) -> Option<PaletteFormat> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "gpl" => Some(PaletteFormat::Gpl),
+            "act" => Some(PaletteFormat::Act),
+            "hex" | "txt" => Some(PaletteFormat::Hex),
+            "pal" if data.starts_with(b"JASC-PAL") => Some(PaletteFormat::Jasc),
+            "pal" | "cgr" | "bin" => Some(PaletteFormat::Bgr555),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PaletteFormat::Gpl => "gpl",
+            PaletteFormat::Jasc => "pal",
+            PaletteFormat::Act => "act",
+            PaletteFormat::Hex => "hex",
+            PaletteFormat::Bgr555 => "pal",
+        }
+    }
+}
+
+impl std::fmt::Display for PaletteFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            PaletteFormat::Gpl => "GIMP (.gpl)",
+            PaletteFormat::Jasc => "JASC-PAL / Aseprite (.pal)",
+            PaletteFormat::Act => "Adobe Color Table (.act)",
+            PaletteFormat::Hex => "Hex list (.hex)",
+            PaletteFormat::Bgr555 => "SNES BGR555 (.pal)",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+pub fn read_palette(data: &[u8], format: PaletteFormat) -> Result<Vec<Color32>, Error> {
+    match format {
+        PaletteFormat::Gpl => read_gpl(&text(data)?),
+        PaletteFormat::Jasc => read_jasc(&text(data)?),
+        PaletteFormat::Act => read_act(data),
+        PaletteFormat::Hex => read_hex(&text(data)?),
+        PaletteFormat::Bgr555 => {
+            if !data.len().is_multiple_of(2) {
+                return Err(Error::InvalidPaletteFile("odd number of bytes in BGR555 data".to_owned()));
+            }
+            Ok(data.chunks(2).map(|c| from_bgr555(u16::from_le_bytes([c[0], c[1]]))).collect())
+        }
+    }
+}
+
+pub fn write_palette_as(palette: &Palette, format: PaletteFormat) -> Vec<u8> {
+    let colors = palette.colors();
+    match format {
+        PaletteFormat::Gpl => {
+            let mut out = String::from("GIMP Palette\nName: snes-paint\nColumns: 16\n#\n");
+            for (i, c) in colors.iter().enumerate() {
+                out += &format!("{:3} {:3} {:3}\tIndex {}\n", c.r(), c.g(), c.b(), i);
+            }
+            out.into_bytes()
+        }
+        PaletteFormat::Jasc => {
+            let mut out = format!("JASC-PAL\r\n0100\r\n{}\r\n", colors.len());
+            for c in colors {
+                out += &format!("{} {} {}\r\n", c.r(), c.g(), c.b());
+            }
+            out.into_bytes()
+        }
+        PaletteFormat::Act => {
+            // always 256 entries, followed by the number of colors actually used and
+            // 0xffff for "no transparent index"
+            let mut out = vec![0u8; 256 * 3];
+            for (i, c) in colors.iter().enumerate() {
+                out[i * 3..i * 3 + 3].copy_from_slice(&[c.r(), c.g(), c.b()]);
+            }
+            out.extend_from_slice(&(colors.len() as u16).to_be_bytes());
+            out.extend_from_slice(&[0xff, 0xff]);
+            out
+        }
+        PaletteFormat::Hex => {
+            let mut out = String::new();
+            for c in colors {
+                out += &format!("{:02x}{:02x}{:02x}\n", c.r(), c.g(), c.b());
+            }
+            out.into_bytes()
+        }
+        PaletteFormat::Bgr555 => write_palette(palette),
+    }
+}
+
+/// Reads a palette file, detecting the format from its extension and contents.
+pub fn load(path: &Path) -> Result<Vec<Color32>, Error> {
+    let data = fs::read(path)?;
+    let format = PaletteFormat::detect(path, &data)
+        .ok_or_else(|| Error::InvalidPaletteFile(format!("unknown palette extension: {}", path.display())))?;
+    read_palette(&data, format)
+}
+
+pub fn save(path: &Path, palette: &Palette, format: PaletteFormat) -> Result<(), Error> {
+    fs::write(path, write_palette_as(palette, format))?;
+    Ok(())
+}
+
+fn text(data: &[u8]) -> Result<String, Error> {
+    String::from_utf8(data.to_vec())
+        .map_err(|_| Error::InvalidPaletteFile("palette file is not valid text".to_owned()))
+}
+
+fn parse_rgb<'a>(mut parts: impl Iterator<Item = &'a str>, line: &str) -> Result<Color32, Error> {
+    let mut channel = || -> Result<u8, Error> {
+        parts.next()
+            .and_then(|p| p.parse::<u8>().ok())
+            .ok_or_else(|| Error::InvalidPaletteFile(format!("bad color entry: {line:?}")))
+    };
+    Ok(Color32::from_rgb(channel()?, channel()?, channel()?))
+}
+
+fn read_gpl(text: &str) -> Result<Vec<Color32>, Error> {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("GIMP Palette") {
+        return Err(Error::InvalidPaletteFile("missing \"GIMP Palette\" header".to_owned()));
+    }
+    let mut colors = vec![];
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") {
+            continue;
+        }
+        // anything after the three channels is the color's name
+        colors.push(parse_rgb(line.split_whitespace(), line)?);
+    }
+    Ok(colors)
+}
+
+fn read_jasc(text: &str) -> Result<Vec<Color32>, Error> {
+    let mut lines = text.lines().map(str::trim);
+    if lines.next() != Some("JASC-PAL") {
+        return Err(Error::InvalidPaletteFile("missing \"JASC-PAL\" header".to_owned()));
+    }
+    // version line, then the color count
+    lines.next();
+    let count = lines.next()
+        .and_then(|l| l.parse::<usize>().ok())
+        .ok_or_else(|| Error::InvalidPaletteFile("bad JASC-PAL color count".to_owned()))?;
+    let mut colors = vec![];
+    for line in lines.filter(|l| !l.is_empty()).take(count) {
+        colors.push(parse_rgb(line.split_whitespace(), line)?);
+    }
+    if colors.len() != count {
+        return Err(Error::InvalidPaletteFile(format!("expected {count} colors, found {}", colors.len())));
+    }
+    Ok(colors)
+}
+
+fn read_act(data: &[u8]) -> Result<Vec<Color32>, Error> {
+    if data.len() < 256 * 3 {
+        return Err(Error::InvalidPaletteFile(format!("ACT files are 768 or 772 bytes, got {}", data.len())));
+    }
+    // the count trailer is optional; without it all 256 entries are in use
+    let count = match data.get(768..770) {
+        Some(&[hi, lo]) => Ord::min(u16::from_be_bytes([hi, lo]) as usize, 256),
+        _ => 256,
+    };
+    Ok(data[..count * 3].chunks(3).map(|c| Color32::from_rgb(c[0], c[1], c[2])).collect())
+}
+
+fn read_hex(text: &str) -> Result<Vec<Color32>, Error> {
+    let mut colors = vec![];
+    for line in text.lines() {
+        let line = line.trim().trim_start_matches('#');
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let rgb = u32::from_str_radix(line, 16)
+            .ok()
+            .filter(|_| line.len() == 6)
+            .ok_or_else(|| Error::InvalidPaletteFile(format!("bad hex color: {line:?}")))?;
+        colors.push(Color32::from_rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+    }
+    Ok(colors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_all_formats() {
+        let mut palette = Palette::new();
+        palette.set_bpp(4).unwrap();
+        for i in 0..16 {
+            // BGR555 drops the low 3 bits, so stick to colors it can represent exactly
+            palette.set_color(i, crate::serde::from_bgr555(0x0421 * i as u16));
+        }
+
+        for format in PaletteFormat::ALL {
+            let data = write_palette_as(&palette, format);
+            let colors = read_palette(&data, format).unwrap();
+            assert_eq!(colors, palette.colors(), "{format}");
+        }
+    }
+
+    #[test]
+    fn test_from_colors_picks_bpp() {
+        assert_eq!(Palette::from_colors(&[Color32::RED; 3]).bpp(), 2);
+        assert_eq!(Palette::from_colors(&[Color32::RED; 16]).bpp(), 4);
+        assert_eq!(Palette::from_colors(&[Color32::RED; 17]).bpp(), 8);
+    }
+}
@@ -0,0 +1,209 @@
+//! Project files: the canvas and palette as editable TOML, so work can be reopened later.
+//!
+//! ```toml
+//! width = 8
+//! height = 8
+//! bpp = 2
+//! palette = ["#ffffff", "#000000", "#710193", "#0147ab"]
+//! pixels = [
+//!     "01230123",
+//!     ...
+//! ]
+//! ```
+//!
+//! Pixels are one hex digit each, or two at 8bpp.
+//!
+//! Animations keep their first frame at the top level, so older versions still open them, with
+//! the rest following as `[[frame]]` tables. Durations are in 1/60 s.
+//!
+//! ```toml
+//! duration = 6
+//!
+//! [[frame]]
+//! duration = 8
+//! pixels = [...]
+//! ```
+
+use std::fs;
+use std::path::Path;
+use eframe::egui::Color32;
+use toml::{Table, Value};
+use crate::paint::{self, Canvas, Frame, Grid, Palette, VecGrid, DEFAULT_FRAME_DURATION};
+use crate::serde::bitmap;
+use crate::Error;
+
+pub const EXTENSION: &str = "snesp";
+
+pub fn write_project(canvas: &Canvas) -> String {
+    let frames = canvas.frames();
+    let grid = frames[0].grid.as_ref();
+    let palette = &canvas.palette;
+    let digits = if palette.bpp() > 4 { 2 } else { 1 };
+    let pixels = |grid: &dyn Grid<usize>| -> String {
+        let mut out = "pixels = [\n".to_owned();
+        for y in 0..grid.height() {
+            let row: String = (0..grid.width()).map(|x| format!("{:0digits$x}", grid.get(x, y))).collect();
+            out += &format!("    \"{row}\",\n");
+        }
+        out + "]\n"
+    };
+
+    let colors: Vec<String> = palette.colors().iter()
+        .map(|c| format!("\"#{:02x}{:02x}{:02x}\"", c.r(), c.g(), c.b()))
+        .collect();
+    let mut out = format!(
+        "width = {}\nheight = {}\nbpp = {}\nduration = {}\npalette = [{}]\n",
+        grid.width(),
+        grid.height(),
+        palette.bpp(),
+        frames[0].duration,
+        colors.join(", "),
+    );
+    out += &pixels(grid);
+    for frame in &frames[1..] {
+        out += &format!("\n[[frame]]\nduration = {}\n", frame.duration);
+        out += &pixels(frame.grid.as_ref());
+    }
+    out
+}
+
+pub fn read_project(text: &str) -> Result<Canvas, Error> {
+    let table: Table = text.parse().map_err(|e| invalid(format!("{e}")))?;
+    let int = |key: &str| -> Result<usize, Error> {
+        table.get(key)
+            .and_then(Value::as_integer)
+            .and_then(|v| usize::try_from(v).ok())
+            .ok_or_else(|| invalid(format!("missing or bad `{key}`")))
+    };
+    let strings = |key: &str| -> Result<Vec<&str>, Error> {
+        table.get(key)
+            .and_then(Value::as_array)
+            .and_then(|a| a.iter().map(Value::as_str).collect::<Option<Vec<_>>>())
+            .ok_or_else(|| invalid(format!("missing or bad `{key}`")))
+    };
+
+    let (width, height, bpp) = (int("width")?, int("height")?, int("bpp")?);
+    if !matches!(bpp, 1..=4 | 8) {
+        return Err(invalid(format!("unsupported bpp {bpp}")));
+    }
+
+    let mut colors = vec![];
+    for color in strings("palette")? {
+        let rgb = color.strip_prefix('#')
+            .filter(|c| c.len() == 6)
+            .and_then(|c| u32::from_str_radix(c, 16).ok())
+            .ok_or_else(|| invalid(format!("bad color {color:?}")))?;
+        colors.push(Color32::from_rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+    }
+    let mut palette = Palette::new();
+    palette.set_bpp(bpp)?;
+    palette.fit_colors(&colors);
+
+    let duration = |table: &Table| -> Result<u16, Error> {
+        match table.get("duration") {
+            None => Ok(DEFAULT_FRAME_DURATION),
+            Some(d) => d.as_integer()
+                .and_then(|d| u16::try_from(d).ok())
+                .filter(|d| *d > 0)
+                .ok_or_else(|| invalid(format!("bad duration {d}"))),
+        }
+    };
+
+    let grid = read_pixels(&strings("pixels")?, width, height, palette.size())?;
+    let mut frames = vec![Frame { grid: Box::new(grid), duration: duration(&table)? }];
+    if let Some(more) = table.get("frame") {
+        let more = more.as_array().ok_or_else(|| invalid("`frame` should be [[frame]] tables".to_owned()))?;
+        for (i, frame) in more.iter().enumerate() {
+            let frame = frame.as_table().ok_or_else(|| invalid(format!("frame {} should be a table", i + 1)))?;
+            let rows = frame.get("pixels")
+                .and_then(Value::as_array)
+                .and_then(|a| a.iter().map(Value::as_str).collect::<Option<Vec<_>>>())
+                .ok_or_else(|| invalid(format!("missing or bad `pixels` in frame {}", i + 1)))?;
+            let grid = read_pixels(&rows, width, height, palette.size())?;
+            frames.push(Frame { grid: Box::new(grid), duration: duration(frame)? });
+        }
+    }
+    Ok(Canvas::from_frames(frames, palette))
+}
+
+fn read_pixels(rows: &[&str], width: usize, height: usize, num_colors: usize) -> Result<VecGrid, Error> {
+    let digits = if num_colors > 16 { 2 } else { 1 };
+    if !paint::valid_size(width) || !paint::valid_size(height) {
+        return Err(invalid(format!("{width}x{height} isn't a canvas size")));
+    }
+    if rows.len() != height {
+        return Err(invalid(format!("expected {height} rows of pixels, found {}", rows.len())));
+    }
+    let mut grid = VecGrid::new(width, height);
+    for (y, row) in rows.iter().enumerate() {
+        if row.len() != width * digits || !row.is_ascii() {
+            return Err(invalid(format!("row {y} should be {} hex digits long", width * digits)));
+        }
+        for x in 0..width {
+            let idx = usize::from_str_radix(&row[x * digits..(x + 1) * digits], 16)
+                .ok()
+                .filter(|idx| *idx < num_colors)
+                .ok_or_else(|| invalid(format!("bad pixel at ({x}, {y})")))?;
+            grid.set(x, y, idx);
+        }
+    }
+    Ok(grid)
+}
+
+fn invalid(msg: String) -> Error {
+    Error::InvalidProjectFile(msg)
+}
+
+pub fn save(path: &Path, canvas: &Canvas) -> Result<(), Error> {
+    fs::write(path, write_project(canvas))?;
+    Ok(())
+}
+
+pub fn is_png(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+}
+
+/// Opens either a project file or a PNG, going by the extension.
+pub fn open(path: &Path) -> Result<Canvas, Error> {
+    if is_png(path) {
+        let (grid, palette) = bitmap::read_png(path, None)?;
+        Ok(Canvas::from_parts(Box::new(grid), palette))
+    } else {
+        read_project(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde::TileOrder;
+
+    #[test]
+    fn test_project_round_trip() {
+        let mut grid = VecGrid::new(16, 8);
+        for x in 0..16 {
+            grid.set(x, x % 8, x % 4);
+        }
+        let mut canvas = Canvas::from_parts(Box::new(grid), Palette::new());
+        canvas.duplicate_frame();
+        *canvas.frame_duration_mut() = 20;
+
+        let text = write_project(&canvas);
+        let read = read_project(&text).unwrap();
+        assert_eq!(read.serialize_frames(TileOrder::Linear).unwrap(), canvas.serialize_frames(TileOrder::Linear).unwrap());
+        assert_eq!(read.palette.colors(), canvas.palette.colors());
+        assert_eq!(read.frames().len(), 2);
+        assert_eq!(read.frames()[1].duration, 20);
+    }
+
+    #[test]
+    fn test_rejects_bad_sizes() {
+        let empty = "width = 0\nheight = 0\nbpp = 2\npalette = []\npixels = []\n";
+        assert!(matches!(read_project(empty), Err(Error::InvalidProjectFile(msg)) if msg.contains("0x0")));
+
+        // rows that match the size aren't enough if it isn't whole tiles
+        let rows = vec![format!("{:?}", "0".repeat(12)); 12].join(", ");
+        let uneven = format!("width = 12\nheight = 12\nbpp = 2\npalette = []\npixels = [{rows}]\n");
+        assert!(matches!(read_project(&uneven), Err(Error::InvalidProjectFile(msg)) if msg.contains("12x12")));
+    }
+}
@@ -0,0 +1,101 @@
+//! C header and Rust module output, for toolchains (PVSnesLib, Rust asset pipelines) that pull
+//! graphics in as arrays.
+
+use std::fmt::Write;
+use crate::serde::asm::label;
+use crate::serde::{tile_size, write_byte_lines};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum SourceLanguage {
+    #[default]
+    C,
+    Rust,
+}
+
+impl SourceLanguage {
+    pub const ALL: [SourceLanguage; 2] = [SourceLanguage::C, SourceLanguage::Rust];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SourceLanguage::C => "h",
+            SourceLanguage::Rust => "rs",
+        }
+    }
+}
+
+impl std::fmt::Display for SourceLanguage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            SourceLanguage::C => "C header (.h)",
+            SourceLanguage::Rust => "Rust module (.rs)",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+/// Renders tile, palette and tilemap data (from [`crate::serde::write_out`] and
+/// [`crate::serde::write_tilemap`]) as `name_tiles`, `name_palette` and `name_tilemap` arrays.
+pub fn write_source(name: &str, v_ram: &[u8], pal: &[u8], tilemap: &[u8], bpp: usize, lang: SourceLanguage) -> String {
+    let name = label(name);
+    let arrays = [
+        (format!("{name}_tiles"), v_ram, Some(tile_size(bpp))),
+        (format!("{name}_palette"), pal, None),
+        (format!("{name}_tilemap"), tilemap, None),
+    ];
+    let mut out = String::new();
+
+    match lang {
+        SourceLanguage::C => {
+            let guard = format!("{}_H", name.to_ascii_uppercase());
+            writeln!(out, "/* {} tiles at {}bpp, {} colors */", v_ram.len() / tile_size(bpp), bpp, pal.len() / 2).unwrap();
+            writeln!(out, "#ifndef {guard}\n#define {guard}\n").unwrap();
+            for (array, bytes, tile_bytes) in arrays {
+                writeln!(out, "#define {}_SIZE {}", array.to_ascii_uppercase(), bytes.len()).unwrap();
+                writeln!(out, "static const unsigned char {array}[{}] = {{", bytes.len()).unwrap();
+                write_bytes(&mut out, bytes, tile_bytes, "/*", " */");
+                writeln!(out, "}};\n").unwrap();
+            }
+            writeln!(out, "#endif /* {guard} */").unwrap();
+        }
+        SourceLanguage::Rust => {
+            writeln!(out, "// {} tiles at {}bpp, {} colors", v_ram.len() / tile_size(bpp), bpp, pal.len() / 2).unwrap();
+            for (array, bytes, tile_bytes) in arrays {
+                writeln!(out).unwrap();
+                writeln!(out, "pub const {}: [u8; {}] = [", array.to_ascii_uppercase(), bytes.len()).unwrap();
+                write_bytes(&mut out, bytes, tile_bytes, "//", "");
+                writeln!(out, "];").unwrap();
+            }
+        }
+    }
+
+    out
+}
+
+/// Writes tiles one to a line, tagged with their tile number, and anything else 16 bytes to a line.
+fn write_bytes(out: &mut String, bytes: &[u8], tile_bytes: Option<usize>, comment_start: &str, comment_end: &str) {
+    write_byte_lines(out, bytes, tile_bytes.unwrap_or(16), |b| format!("0x{b:02x},"), |i, line| {
+        match tile_bytes {
+            Some(_) => format!("    {} {comment_start} tile {i}{comment_end}", line.join(" ")),
+            None => format!("    {}", line.join(" ")),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_source() {
+        let tiles = [0u8; 32];
+        let c = write_source("hero", &tiles, &[0, 0], &[0, 0], 2, SourceLanguage::C);
+        // static, so every file that includes the header gets its own copy instead of a clash
+        assert!(c.contains("static const unsigned char hero_tiles[32] = {"));
+        assert!(c.contains("    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* tile 1 */"));
+
+        // no inner doc comment, so it works with include!()
+        let rust = write_source("hero", &tiles, &[0, 0], &[0, 0], 2, SourceLanguage::Rust);
+        assert!(rust.starts_with("// 2 tiles at 2bpp, 1 colors\n"));
+        assert!(rust.contains("pub const HERO_PALETTE: [u8; 2] = [\n    0x00, 0x00,\n];"));
+    }
+}
@@ -0,0 +1,220 @@
+//! What's remembered between runs: sidebar choices, zoom, the last directory and palette, and
+//! recently opened projects. Lives next to the keymap as `settings.toml`, and is rewritten
+//! whenever something in it changes, so a crash doesn't lose it either.
+
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use eframe::egui::Color32;
+use toml::{Table, Value};
+use crate::app::SideBarType;
+use crate::serde::asm::Assembler;
+use crate::serde::compress::Compression;
+use crate::serde::palette::PaletteFormat;
+use crate::serde::source::SourceLanguage;
+use crate::serde::TileOrder;
+use crate::Error;
+
+pub const FILE_NAME: &str = "settings.toml";
+/// How many projects the File sidebar lists.
+pub const MAX_RECENT: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Settings {
+    pub side_bar: SideBarType,
+    /// Canvas zoom, in screen pixels per pixel.
+    pub pixel_width: u32,
+    pub palette_format: PaletteFormat,
+    pub keep_palette_bpp: bool,
+    pub assembler: Assembler,
+    pub source_language: SourceLanguage,
+    pub compression: Compression,
+    pub tile_order: TileOrder,
+    /// Where the last file dialog ended up.
+    pub last_dir: Option<PathBuf>,
+    /// The palette when the app was last used, so it comes back with it.
+    pub palette: Option<Vec<Color32>>,
+    /// Most recent first.
+    pub recent: Vec<PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            side_bar: SideBarType::default(),
+            pixel_width: 20,
+            palette_format: PaletteFormat::default(),
+            keep_palette_bpp: false,
+            assembler: Assembler::default(),
+            source_language: SourceLanguage::default(),
+            compression: Compression::default(),
+            tile_order: TileOrder::default(),
+            last_dir: None,
+            palette: None,
+            recent: vec![],
+        }
+    }
+}
+
+/// Where settings and the keymap go, `~/.config/snes-paint` on Linux.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("snes-paint"))
+}
+
+impl Settings {
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join(FILE_NAME))
+    }
+
+    /// The saved settings, or the defaults if there aren't any.
+    pub fn load() -> Result<Settings, Error> {
+        match Settings::path() {
+            Some(path) if path.exists() => Ok(Settings::parse(&fs::read_to_string(path)?)),
+            _ => Ok(Settings::default()),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let Some(path) = Settings::path() else { return Ok(()) };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.write())?;
+        Ok(())
+    }
+
+    /// Reads what it can, leaving anything missing or unreadable at its default. Settings aren't
+    /// worth refusing to start over.
+    pub fn parse(text: &str) -> Settings {
+        let mut settings = Settings::default();
+        let Ok(table) = text.parse::<Table>() else { return settings };
+        let str = |key: &str| table.get(key).and_then(Value::as_str);
+        let paths = |key: &str| -> Vec<PathBuf> {
+            table.get(key)
+                .and_then(Value::as_array)
+                .map(|a| a.iter().filter_map(Value::as_str).map(PathBuf::from).collect())
+                .unwrap_or_default()
+        };
+
+        if let Some(side_bar) = pick(&SideBarType::ALL, str("side_bar")) {
+            settings.side_bar = side_bar;
+        }
+        if let Some(width) = table.get("zoom").and_then(Value::as_integer).and_then(|w| u32::try_from(w).ok()) {
+            settings.pixel_width = width.clamp(1, 64);
+        }
+        if let Some(format) = pick(&PaletteFormat::ALL, str("palette_format")) {
+            settings.palette_format = format;
+        }
+        if let Some(keep) = table.get("keep_palette_bpp").and_then(Value::as_bool) {
+            settings.keep_palette_bpp = keep;
+        }
+        if let Some(assembler) = pick(&Assembler::ALL, str("assembler")) {
+            settings.assembler = assembler;
+        }
+        if let Some(lang) = pick(&SourceLanguage::ALL, str("source_language")) {
+            settings.source_language = lang;
+        }
+        if let Some(compression) = pick(&Compression::ALL, str("compression")) {
+            settings.compression = compression;
+        }
+        if let Some(order) = pick(&TileOrder::ALL, str("tile_order")) {
+            settings.tile_order = order;
+        }
+        settings.last_dir = str("last_dir").map(PathBuf::from);
+        settings.palette = table.get("palette")
+            .and_then(Value::as_array)
+            .and_then(|a| a.iter().map(|c| c.as_str().and_then(parse_color)).collect::<Option<Vec<_>>>())
+            .filter(|colors| !colors.is_empty());
+        settings.recent = paths("recent");
+        settings.recent.truncate(MAX_RECENT);
+        settings
+    }
+
+    pub fn write(&self) -> String {
+        let mut table = Table::new();
+        let mut set = |key: &str, value: Value| {
+            table.insert(key.to_owned(), value);
+        };
+        set("side_bar", Value::String(self.side_bar.to_string()));
+        set("zoom", Value::Integer(self.pixel_width.into()));
+        set("palette_format", Value::String(self.palette_format.to_string()));
+        set("keep_palette_bpp", Value::Boolean(self.keep_palette_bpp));
+        set("assembler", Value::String(self.assembler.to_string()));
+        set("source_language", Value::String(self.source_language.to_string()));
+        set("compression", Value::String(self.compression.to_string()));
+        set("tile_order", Value::String(self.tile_order.to_string()));
+        if let Some(dir) = &self.last_dir {
+            set("last_dir", Value::String(dir.to_string_lossy().into_owned()));
+        }
+        if let Some(colors) = &self.palette {
+            let colors = colors.iter().map(|c| Value::String(format!("#{:02x}{:02x}{:02x}", c.r(), c.g(), c.b())));
+            set("palette", Value::Array(colors.collect()));
+        }
+        let recent = self.recent.iter().map(|p| Value::String(p.to_string_lossy().into_owned()));
+        set("recent", Value::Array(recent.collect()));
+        table.to_string()
+    }
+
+    /// A file dialog that starts where the last one left off.
+    pub fn dialog(&self) -> rfd::FileDialog {
+        match &self.last_dir {
+            Some(dir) => rfd::FileDialog::new().set_directory(dir),
+            None => rfd::FileDialog::new(),
+        }
+    }
+
+    pub fn remember_dir(&mut self, file: &Path) {
+        self.last_dir = file.parent().map(Path::to_path_buf);
+    }
+
+    /// Moves a project to the top of the recent list.
+    pub fn add_recent(&mut self, file: &Path) {
+        let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        self.recent.retain(|p| *p != file);
+        self.recent.insert(0, file);
+        self.recent.truncate(MAX_RECENT);
+    }
+}
+
+/// The option whose `Display` is `name`.
+fn pick<T: Copy + Display>(all: &[T], name: Option<&str>) -> Option<T> {
+    let name = name?;
+    all.iter().copied().find(|t| t.to_string() == name)
+}
+
+fn parse_color(color: &str) -> Option<Color32> {
+    let rgb = color.strip_prefix('#')
+        .filter(|c| c.len() == 6)
+        .and_then(|c| u32::from_str_radix(c, 16).ok())?;
+    Some(Color32::from_rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_and_recent() {
+        let mut settings = Settings {
+            side_bar: SideBarType::Rom,
+            pixel_width: 12,
+            compression: Compression::Lz2,
+            tile_order: TileOrder::Sprite16,
+            assembler: Assembler::Asar,
+            last_dir: Some(PathBuf::from("/tmp/art")),
+            palette: Some(vec![Color32::WHITE, Color32::from_rgb(0x71, 0x01, 0x93)]),
+            ..Default::default()
+        };
+        for n in 0..10 {
+            settings.add_recent(Path::new(&format!("/nowhere/{n}.snesp")));
+        }
+        settings.add_recent(Path::new("/nowhere/5.snesp"));
+        assert_eq!(settings.recent.len(), MAX_RECENT);
+        assert_eq!(settings.recent[0], Path::new("/nowhere/5.snesp"));
+        assert_eq!(settings.recent[1], Path::new("/nowhere/9.snesp"));
+
+        assert_eq!(Settings::parse(&settings.write()), settings);
+        // junk falls back to the defaults rather than failing
+        assert_eq!(Settings::parse("zoom = \"big\"\nside_bar = 3"), Settings::default());
+    }
+}
@@ -0,0 +1,225 @@
+//! Metasprites: a character made of several OBJs placed around an origin, and the two ways of
+//! getting them to the SNES.
+//!
+//! OAM export is a full 544 byte OAM image (128 four byte entries, then the 32 byte high table),
+//! ready to DMA. Unused entries are parked off screen at y=240.
+//!
+//! The metasprite table is for engines that build OAM themselves: a count byte, then five bytes
+//! per OBJ:
+//!
+//! ```text
+//! dx     signed x offset from the origin
+//! dy     signed y offset from the origin
+//! tile   low 8 bits of the tile number
+//! attr   vhoopppN, same as the fourth OAM byte (N is tile bit 8)
+//! size   0 for small, 1 for large
+//! ```
+
+use crate::Error;
+
+/// Number of entries in OAM.
+pub const OAM_ENTRIES: usize = 128;
+/// Off screen y for unused entries.
+const HIDDEN_Y: u8 = 240;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum ObjSize {
+    /// 8x8, assuming the usual OBSEL setting of 8x8/16x16.
+    #[default]
+    Small,
+    /// 16x16. Tiles `n`, `n+1`, `n+16` and `n+17`.
+    Large,
+}
+
+impl ObjSize {
+    pub fn pixels(&self) -> i32 {
+        match self {
+            ObjSize::Small => 8,
+            ObjSize::Large => 16,
+        }
+    }
+}
+
+impl std::fmt::Display for ObjSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            ObjSize::Small => "8x8",
+            ObjSize::Large => "16x16",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+/// One hardware sprite, relative to the metasprite's origin.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Obj {
+    pub x: i8,
+    pub y: i8,
+    /// Tile number, 0-511.
+    pub tile: u16,
+    /// OBJ palette, 0-7 (CGRAM sub-palettes 8-15).
+    pub palette: u8,
+    /// 0-3, 3 being in front of every BG.
+    pub priority: u8,
+    pub h_flip: bool,
+    pub v_flip: bool,
+    pub size: ObjSize,
+}
+
+impl Obj {
+    /// The fourth OAM byte: `vhoopppN`.
+    pub fn attributes(&self) -> u8 {
+        (self.v_flip as u8) << 7
+            | (self.h_flip as u8) << 6
+            | (self.priority & 0b11) << 4
+            | (self.palette & 0b111) << 1
+            | ((self.tile >> 8) & 1) as u8
+    }
+
+    /// Which tile (counting 8x8 tiles) covers pixel (x, y) of the OBJ, and where in that tile
+    /// the pixel is, with flips applied.
+    pub fn tile_at(&self, x: usize, y: usize) -> (usize, usize, usize) {
+        let size = self.size.pixels() as usize;
+        let x = if self.h_flip { size - 1 - x } else { x };
+        let y = if self.v_flip { size - 1 - y } else { y };
+        let tile = self.tile as usize + x / 8 + (y / 8) * 16;
+        (tile, x % 8, y % 8)
+    }
+
+    /// The canvas pixel that pixel (x, y) of the OBJ comes from, given where each VRAM tile slot is
+    /// on the canvas (see [`TileOrder::layout`](crate::serde::TileOrder::layout)). None if that
+    /// tile isn't on the canvas.
+    pub fn canvas_pixel(&self, x: usize, y: usize, layout: &[Option<(usize, usize)>]) -> Option<(usize, usize)> {
+        let (tile, tx, ty) = self.tile_at(x, y);
+        let (col, row) = (*layout.get(tile)?)?;
+        Some((col * 8 + tx, row * 8 + ty))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum SpriteExport {
+    #[default]
+    Oam,
+    Table,
+}
+
+impl SpriteExport {
+    pub const ALL: [SpriteExport; 2] = [SpriteExport::Oam, SpriteExport::Table];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SpriteExport::Oam => "oam",
+            SpriteExport::Table => "msp",
+        }
+    }
+}
+
+impl std::fmt::Display for SpriteExport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            SpriteExport::Oam => "OAM image (.oam)",
+            SpriteExport::Table => "Metasprite table (.msp)",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Metasprite {
+    pub objs: Vec<Obj>,
+}
+
+impl Metasprite {
+    /// Returns: a full OAM image with the metasprite's origin at screen position `origin`.
+    pub fn write_oam(&self, origin: (i32, i32)) -> Result<Vec<u8>, Error> {
+        if self.objs.len() > OAM_ENTRIES {
+            return Err(Error::InvalidMetasprite(format!("{} OBJs won't fit in OAM", self.objs.len())));
+        }
+        let mut low = Vec::with_capacity(OAM_ENTRIES * 4);
+        let mut high = vec![0u8; OAM_ENTRIES / 4];
+        for (n, obj) in self.objs.iter().enumerate() {
+            // x is 9 bits and wraps, so a little to the left of the screen is x=511
+            let x = (origin.0 + obj.x as i32).rem_euclid(512);
+            let y = (origin.1 + obj.y as i32).rem_euclid(256);
+            low.extend_from_slice(&[x as u8, y as u8, obj.tile as u8, obj.attributes()]);
+            let bits = (x >> 8) as u8 | ((obj.size == ObjSize::Large) as u8) << 1;
+            high[n / 4] |= bits << ((n % 4) * 2);
+        }
+        for _ in self.objs.len()..OAM_ENTRIES {
+            low.extend_from_slice(&[0, HIDDEN_Y, 0, 0]);
+        }
+        low.extend(high);
+        Ok(low)
+    }
+
+    /// Returns: the compact metasprite table described in the module docs.
+    pub fn write_table(&self) -> Result<Vec<u8>, Error> {
+        let count = u8::try_from(self.objs.len())
+            .map_err(|_| Error::InvalidMetasprite(format!("{} OBJs is too many for one table", self.objs.len())))?;
+        let mut table = vec![count];
+        for obj in &self.objs {
+            table.extend_from_slice(&[
+                obj.x as u8,
+                obj.y as u8,
+                obj.tile as u8,
+                obj.attributes(),
+                (obj.size == ObjSize::Large) as u8,
+            ]);
+        }
+        Ok(table)
+    }
+
+    pub fn export(&self, format: SpriteExport, origin: (i32, i32)) -> Result<Vec<u8>, Error> {
+        match format {
+            SpriteExport::Oam => self.write_oam(origin),
+            SpriteExport::Table => self.write_table(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde::TileOrder;
+
+    #[test]
+    fn test_oam_and_table() {
+        let sprite = Metasprite {
+            objs: vec![
+                Obj { x: -8, y: -16, tile: 0x102, palette: 2, priority: 3, h_flip: true, size: ObjSize::Large, ..Default::default() },
+                Obj { x: 0, y: 0, tile: 4, ..Default::default() },
+            ],
+        };
+
+        let oam = sprite.write_oam((4, 100)).unwrap();
+        assert_eq!(oam.len(), 544);
+        // x = -4 wraps around to 508, so bit 8 of x goes in the high table
+        assert_eq!(oam[0..4], [0xfc, 84, 0x02, 0b0111_0101]);
+        assert_eq!(oam[4..8], [4, 100, 4, 0]);
+        assert_eq!(oam[8..12], [0, HIDDEN_Y, 0, 0]);
+        assert_eq!(oam[512], 0b0000_0011);
+
+        let table = sprite.write_table().unwrap();
+        assert_eq!(table, [2, 0xf8, 0xf0, 0x02, 0b0111_0101, 1, 0, 0, 4, 0, 0]);
+
+        // flipped 16x16: the top left pixel is the top right tile's last column
+        assert_eq!(sprite.objs[0].tile_at(0, 0), (0x102 + 1, 7, 0));
+    }
+
+    #[test]
+    fn test_canvas_pixel_follows_layout() {
+        // a 64x32 canvas: 8 tiles a row, so VRAM tile 16 is the start of the third row
+        let obj = Obj { tile: 1, size: ObjSize::Large, ..Default::default() };
+        let linear = TileOrder::Linear.layout(8, 4);
+        assert_eq!(obj.canvas_pixel(0, 0, &linear), Some((8, 0)));
+        assert_eq!(obj.canvas_pixel(9, 10, &linear), Some((17, 18)));
+
+        // 16x16 order puts the bottom half of sprite 0 at tile 16 but right under it on the canvas
+        let sprites = TileOrder::Sprite16.layout(8, 4);
+        let obj = Obj { tile: 0, size: ObjSize::Large, ..Default::default() };
+        assert_eq!(obj.canvas_pixel(3, 12, &sprites), Some((3, 12)));
+        // tile 40 would be past the bottom of the canvas
+        let obj = Obj { tile: 40, ..Default::default() };
+        assert_eq!(obj.canvas_pixel(0, 0, &linear), None);
+    }
+}
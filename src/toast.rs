@@ -0,0 +1,78 @@
+//! Short messages that pop up in the corner of the window and go away on their own, for errors
+//! and the results of things that don't otherwise show.
+
+use eframe::egui::{Align2, Area, Color32, Context, Frame, Id, Order, RichText, Sense, Vec2};
+use crate::Error;
+
+/// How long a toast stays up.
+pub const TOAST_SECONDS: f64 = 6.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Level {
+    Info,
+    Error,
+}
+
+struct Toast {
+    text: String,
+    level: Level,
+    /// When it was first drawn. Toasts are pushed without a `Context` to hand, so the clock
+    /// starts then.
+    shown_at: Option<f64>,
+}
+
+#[derive(Default)]
+pub(crate) struct Toasts {
+    toasts: Vec<Toast>,
+}
+
+impl Toasts {
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.push(text.into(), Level::Info);
+    }
+
+    /// Shows an error with what was being tried, like "Couldn't save project: ...".
+    pub fn error(&mut self, context: &str, error: &Error) {
+        self.push(format!("{context}: {error}"), Level::Error);
+    }
+
+    fn push(&mut self, text: String, level: Level) {
+        // still useful when running from a terminal
+        if level == Level::Error {
+            eprintln!("{text}");
+        }
+        self.toasts.push(Toast { text, level, shown_at: None });
+    }
+
+    pub fn show(&mut self, ctx: &Context) {
+        let now = ctx.input(|i| i.time);
+        self.toasts.retain(|t| t.shown_at.is_none_or(|at| now - at < TOAST_SECONDS));
+        if self.toasts.is_empty() {
+            return;
+        }
+        Area::new(Id::new("Toasts"))
+            .order(Order::Foreground)
+            .anchor(Align2::RIGHT_BOTTOM, Vec2::new(-16.0, -48.0))
+            .show(ctx, |ui| {
+                let mut dismissed = None;
+                for (n, toast) in self.toasts.iter_mut().enumerate() {
+                    toast.shown_at.get_or_insert(now);
+                    let color = match toast.level {
+                        Level::Info => ui.visuals().text_color(),
+                        Level::Error => Color32::LIGHT_RED,
+                    };
+                    let response = Frame::popup(ui.style())
+                        .show(ui, |ui| ui.label(RichText::new(&toast.text).color(color)))
+                        .response;
+                    if response.interact(Sense::click()).on_hover_text("click to dismiss").clicked() {
+                        dismissed = Some(n);
+                    }
+                }
+                if let Some(n) = dismissed {
+                    self.toasts.remove(n);
+                }
+            });
+        // come back to clear them even if nothing else happens
+        ctx.request_repaint_after(std::time::Duration::from_secs_f64(TOAST_SECONDS));
+    }
+}
@@ -0,0 +1,307 @@
+//! Vim-style modal editing on top of the canvas cursor.
+//!
+//! Normal mode:
+//!
+//! [count]h/j/k/l: move the cursor
+//! [count]w/b: jump to the next/previous 8x8 tile
+//! gg/G: jump to the first/last row, or row [count]
+//! v: visual mode, i: insert mode
+//! p: paste the yanked pixels with their top left corner at the cursor
+//!
+//! Visual mode moves the same way, selecting the rectangle between where `v` was pressed and
+//! the cursor. y yanks it, d yanks it and clears it to color 0, p pastes over it.
+//!
+//! Insert mode moves the same way too, painting every pixel the cursor passes over.
+//!
+//! escape goes back to normal mode from anywhere.
+//!
+//! ctrl+c and ctrl+v copy the selection and paste it in any mode, through the same register as
+//! y and p. The app keeps one `Vim` shared by all tabs (mode, visual anchor and register alike),
+//! so pixels yanked in one document paste into another.
+
+use eframe::egui::{Event, Key, Modifiers, Ui};
+use crate::keymap::{Action, Keymap};
+use crate::paint::{Canvas, VecGrid};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Mode {
+    #[default]
+    Normal,
+    Visual,
+    Insert,
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            Mode::Normal => "NORMAL",
+            Mode::Visual => "VISUAL",
+            Mode::Insert => "INSERT",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Vim {
+    mode: Mode,
+    /// Count typed so far, like the 5 in `5l`.
+    count: Option<usize>,
+    /// Whether the last key was the first `g` of `gg`.
+    pending_g: bool,
+    /// Where visual mode started.
+    anchor: (usize, usize),
+    /// Pixels from the last yank or delete.
+    register: Option<VecGrid>,
+}
+
+impl Vim {
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// What's been typed towards the next command, for showing next to the mode.
+    pub fn pending(&self) -> String {
+        let count = self.count.map(|c| c.to_string()).unwrap_or_default();
+        count + if self.pending_g { "g" } else { "" }
+    }
+
+    pub fn register(&self) -> Option<&VecGrid> {
+        self.register.as_ref()
+    }
+
+    /// Handles this frame's key presses, unless a text field has the keyboard.
+    pub fn update(&mut self, canvas: &mut Canvas, ui: &mut Ui, keymap: &Keymap) {
+        if ui.ctx().wants_keyboard_input() {
+            return;
+        }
+        let keys: Vec<(Key, Modifiers)> = ui.input(|i| i.events.iter().filter_map(|e| match e {
+            Event::Key { key, pressed: true, modifiers, .. } => Some((*key, *modifiers)),
+            // the platform turns its copy and paste shortcuts into these instead of key presses
+            Event::Copy => Some((Key::C, Modifiers::COMMAND)),
+            Event::Paste(_) => Some((Key::V, Modifiers::COMMAND)),
+            _ => None,
+        }).collect());
+        for (key, modifiers) in keys {
+            if self.handle(key, modifiers, canvas, keymap) {
+                ui.input_mut(|i| i.consume_key(modifiers, key));
+            }
+        }
+    }
+
+    /// Returns whether the key meant anything.
+    pub fn handle(&mut self, key: Key, modifiers: Modifiers, canvas: &mut Canvas, keymap: &Keymap) -> bool {
+        let is = |action: Action| {
+            let shortcut = keymap.get(action);
+            shortcut.logical_key == key && modifiers.matches_exact(shortcut.modifiers)
+        };
+
+        if is(Action::NormalMode) {
+            // also drops a selection made with the mouse
+            canvas.selection = None;
+            self.mode = Mode::Normal;
+            self.reset();
+            return true;
+        }
+
+        // counts, where a leading 0 isn't one
+        if let Some(digit) = digit(key).filter(|_| modifiers.is_none()) {
+            if digit != 0 || self.count.is_some() {
+                self.count = Some(self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                return true;
+            }
+        }
+
+        let count = self.count.unwrap_or(1);
+        let (width, height) = (canvas.grid().width(), canvas.grid().height());
+        let (x, y) = canvas.cursor();
+
+        let target = if is(Action::CursorLeft) {
+            Some((x.saturating_sub(count), y))
+        } else if is(Action::CursorRight) {
+            Some((Ord::min(x + count, width - 1), y))
+        } else if is(Action::CursorUp) {
+            Some((x, y.saturating_sub(count)))
+        } else if is(Action::CursorDown) {
+            Some((x, Ord::min(y + count, height - 1)))
+        } else if is(Action::NextTile) {
+            let tiles_wide = width.div_ceil(8);
+            let tile = Ord::min((y / 8) * tiles_wide + x / 8 + count, (height.div_ceil(8) * tiles_wide) - 1);
+            Some(((tile % tiles_wide) * 8, (tile / tiles_wide) * 8))
+        } else if is(Action::PreviousTile) {
+            let tiles_wide = width.div_ceil(8);
+            let tile = ((y / 8) * tiles_wide + x / 8).saturating_sub(count);
+            Some(((tile % tiles_wide) * 8, (tile / tiles_wide) * 8))
+        } else if is(Action::GotoLastRow) {
+            Some((x, self.count.map_or(height - 1, |row| row.clamp(1, height) - 1)))
+        } else if is(Action::GotoFirstRow) {
+            if !self.pending_g {
+                self.pending_g = true;
+                return true;
+            }
+            Some((x, self.count.map_or(0, |row| row.clamp(1, height) - 1)))
+        } else {
+            None
+        };
+
+        if let Some(target) = target {
+            self.move_to(target, canvas);
+            self.reset();
+            return true;
+        }
+
+        if is(Action::CopySelection) {
+            if let Some(copy) = canvas.copy_selection() {
+                self.register = Some(copy);
+            }
+            self.reset();
+            return true;
+        }
+        if is(Action::PasteSelection) {
+            // over the selection if there is one, like p in visual mode
+            let at = canvas.selection_bounds().map_or((x, y), |(sx, sy, _, _)| (sx, sy));
+            self.paste(at, canvas);
+            self.reset();
+            return true;
+        }
+
+        let handled = match self.mode {
+            Mode::Normal if is(Action::VisualMode) => {
+                self.mode = Mode::Visual;
+                self.anchor = (x, y);
+                canvas.selection = Some((self.anchor, self.anchor));
+                true
+            }
+            Mode::Normal if is(Action::InsertMode) => {
+                self.mode = Mode::Insert;
+                true
+            }
+            Mode::Normal if is(Action::Paste) => {
+                self.paste((x, y), canvas);
+                true
+            }
+            Mode::Visual if is(Action::VisualMode) => {
+                self.mode = Mode::Normal;
+                canvas.selection = None;
+                true
+            }
+            Mode::Visual if is(Action::Yank) || is(Action::Delete) => {
+                self.register = canvas.copy_selection();
+                if is(Action::Delete) {
+                    canvas.clear_selection();
+                }
+                self.mode = Mode::Normal;
+                canvas.selection = None;
+                true
+            }
+            Mode::Visual if is(Action::Paste) => {
+                if let Some((sx, sy, _, _)) = canvas.selection_bounds() {
+                    self.paste((sx, sy), canvas);
+                }
+                self.mode = Mode::Normal;
+                canvas.selection = None;
+                true
+            }
+            _ => false,
+        };
+        self.reset();
+        handled
+    }
+
+    fn move_to(&mut self, target: (usize, usize), canvas: &mut Canvas) {
+        let from = canvas.cursor();
+        canvas.set_cursor(target);
+        match self.mode {
+            Mode::Normal => {}
+            Mode::Visual => canvas.selection = Some((self.anchor, target)),
+            Mode::Insert => {
+                // paint the whole way there, not just where the cursor lands
+                for (px, py) in path(from, target) {
+                    canvas.paint(px, py);
+                }
+            }
+        }
+    }
+
+    fn paste(&self, at: (usize, usize), canvas: &mut Canvas) {
+        if let Some(register) = &self.register {
+            canvas.paste(register, at);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.count = None;
+        self.pending_g = false;
+    }
+}
+
+/// The pixels between two points on the same row or column, excluding the start.
+fn path(from: (usize, usize), to: (usize, usize)) -> Vec<(usize, usize)> {
+    let step = |a: usize, b: usize| -> Vec<usize> {
+        if a <= b { (a..=b).collect() } else { (b..=a).rev().collect() }
+    };
+    if from.1 == to.1 {
+        step(from.0, to.0).into_iter().skip(1).map(|x| (x, to.1)).collect()
+    } else if from.0 == to.0 {
+        step(from.1, to.1).into_iter().skip(1).map(|y| (to.0, y)).collect()
+    } else {
+        vec![to]
+    }
+}
+
+fn digit(key: Key) -> Option<usize> {
+    let digits = [Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4, Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9];
+    digits.iter().position(|k| *k == key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paint::Grid;
+
+    fn press(vim: &mut Vim, canvas: &mut Canvas, keys: &[(Key, Modifiers)]) {
+        let keymap = Keymap::default();
+        for (key, modifiers) in keys {
+            vim.handle(*key, *modifiers, canvas, &keymap);
+        }
+    }
+
+    #[test]
+    fn test_counts_motions_and_yank_paste() {
+        let none = Modifiers::NONE;
+        let mut canvas = Canvas::new();
+        canvas.set_size(16, 16).unwrap();
+        let mut vim = Vim::default();
+
+        press(&mut vim, &mut canvas, &[(Key::Num5, none), (Key::L, none)]);
+        assert_eq!(canvas.cursor(), (5, 0));
+        press(&mut vim, &mut canvas, &[(Key::G, Modifiers::SHIFT)]);
+        assert_eq!(canvas.cursor(), (5, 15));
+        press(&mut vim, &mut canvas, &[(Key::G, none), (Key::G, none)]);
+        assert_eq!(canvas.cursor(), (5, 0));
+        press(&mut vim, &mut canvas, &[(Key::W, none)]);
+        assert_eq!(canvas.cursor(), (8, 0));
+        press(&mut vim, &mut canvas, &[(Key::W, none)]);
+        assert_eq!(canvas.cursor(), (0, 8));
+
+        // insert mode paints where the cursor moves to, not where it started, so this is one
+        // pixel. Yank it with the blank one before it and put both somewhere else
+        canvas.color_idx = 2;
+        press(&mut vim, &mut canvas, &[(Key::I, none), (Key::L, none), (Key::Escape, none)]);
+        assert_eq!(canvas.grid().get(0, 8), 0);
+        assert_eq!(canvas.grid().get(1, 8), 2);
+        press(&mut vim, &mut canvas, &[(Key::H, none), (Key::V, none), (Key::L, none), (Key::Y, none)]);
+        assert_eq!(vim.mode(), Mode::Normal);
+        assert_eq!(vim.register().map(|r| (r.width(), r.height(), r.get(0, 0), r.get(1, 0))), Some((2, 1, 0, 2)));
+        press(&mut vim, &mut canvas, &[(Key::G, Modifiers::SHIFT), (Key::P, none)]);
+        assert_eq!(canvas.grid().get(2, 15), 2);
+
+        // ctrl+c in one canvas, ctrl+v in another
+        canvas.selection = Some(((2, 15), (2, 15)));
+        press(&mut vim, &mut canvas, &[(Key::C, Modifiers::COMMAND)]);
+        let mut other = Canvas::new();
+        other.set_cursor((4, 4));
+        press(&mut vim, &mut other, &[(Key::V, Modifiers::COMMAND)]);
+        assert_eq!(other.grid().get(4, 4), 2);
+    }
+}